@@ -0,0 +1,308 @@
+//! Arpeggiator holding incoming notes and replaying them in order.
+//! Note ordering and step timing are plain computations (no embassy types)
+//! so they can be exercised on the host.
+
+use heapless::Vec;
+use num_enum::FromPrimitive;
+
+use crate::chaos::WyRand;
+
+/// Max number of notes held at once
+const MAX_HELD: usize = 16;
+
+/// Worst case is UpDown: every held note over every octave, up then back down
+const MAX_SEQ: usize = MAX_HELD * MAX_OCTAVES as usize * 2;
+
+const MAX_OCTAVES: u8 = 4;
+
+const MAX_NOTE: u8 = 127;
+
+#[derive(Debug, FromPrimitive, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+#[derive(defmt::Format)]
+pub enum ArpMode {
+    #[num_enum(default)]
+    Up,
+    Down,
+    UpDown,
+    Random,
+    AsPlayed,
+}
+
+/// A single arpeggiator step, to be played by the caller
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ArpStep {
+    pub note: u8,
+    pub velocity: u8,
+    /// How long the note stays on
+    pub gate_ms: u32,
+    /// How long to wait after note off before next step
+    pub rest_ms: u32,
+}
+
+#[derive(Debug)]
+pub struct Arp {
+    enabled: bool,
+    latch: bool,
+    mode: ArpMode,
+    octaves: u8,
+    step_ms: u32,
+    // between 0 and 1
+    gate: f32,
+    velocity: u8,
+    // notes played by the arp, in the order they were received
+    held: Vec<u8, MAX_HELD>,
+    // notes physically held down, used to release notes when unlatching
+    keys: Vec<u8, MAX_HELD>,
+    pos: usize,
+    // for Random mode
+    chaos: WyRand,
+}
+
+impl Default for Arp {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            latch: false,
+            mode: ArpMode::Up,
+            octaves: 1,
+            step_ms: 125,
+            gate: 0.5,
+            velocity: 100,
+            held: Vec::new(),
+            keys: Vec::new(),
+            pos: 0,
+            chaos: WyRand::default(),
+        }
+    }
+}
+
+impl Arp {
+    pub fn note_on(&mut self, note: u8, velocity: u8) {
+        if self.latch && self.keys.is_empty() {
+            // fresh chord replaces latched notes
+            self.held.clear();
+            self.pos = 0;
+        }
+        if !self.keys.contains(&note) {
+            let _ = self.keys.push(note);
+        }
+        if !self.held.contains(&note) {
+            let _ = self.held.push(note);
+        }
+        self.velocity = velocity;
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        self.keys.retain(|n| *n != note);
+        if !self.latch {
+            self.held.retain(|n| *n != note);
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.pos = 0;
+    }
+
+    pub fn is_latch(&self) -> bool {
+        self.latch
+    }
+
+    pub fn set_latch(&mut self, latch: bool) {
+        self.latch = latch;
+        if !latch {
+            let keys = &self.keys;
+            self.held.retain(|n| keys.contains(n));
+        }
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.chaos = WyRand::new(seed);
+    }
+
+    pub fn get_mode(&self) -> ArpMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ArpMode) {
+        self.mode = mode;
+    }
+
+    pub fn get_octaves(&self) -> u8 {
+        self.octaves
+    }
+
+    pub fn set_octaves(&mut self, octaves: u8) {
+        self.octaves = octaves.max(1).min(MAX_OCTAVES);
+    }
+
+    pub fn get_step_ms(&self) -> u32 {
+        self.step_ms
+    }
+
+    pub fn set_step_ms(&mut self, step_ms: u32) {
+        self.step_ms = step_ms.max(1);
+    }
+
+    pub fn get_gate(&self) -> f32 {
+        self.gate
+    }
+
+    pub fn set_gate(&mut self, gate: f32) {
+        self.gate = gate.max(0.05).min(1.0);
+    }
+
+    /// Notes in the order they will be played, expanded over all octaves
+    pub fn sequence(&self) -> Vec<u8, MAX_SEQ> {
+        let mut base: Vec<u8, MAX_HELD> = self.held.clone();
+        if self.mode != ArpMode::AsPlayed {
+            base.sort_unstable();
+        }
+
+        let mut seq: Vec<u8, MAX_SEQ> = Vec::new();
+        for oct in 0..self.octaves {
+            for note in &base {
+                let note = *note as u16 + oct as u16 * 12;
+                if note <= MAX_NOTE as u16 {
+                    let _ = seq.push(note as u8);
+                }
+            }
+        }
+
+        match self.mode {
+            ArpMode::Down => seq.reverse(),
+            ArpMode::UpDown if seq.len() > 2 => {
+                // don't repeat top and bottom notes when changing direction
+                for idx in (1..seq.len() - 1).rev() {
+                    let _ = seq.push(seq[idx]);
+                }
+            }
+            _ => {}
+        }
+        seq
+    }
+
+    /// Advance the arp by one step
+    /// Returns None if the arp is disabled or no note is held
+    pub fn next_step(&mut self) -> Option<ArpStep> {
+        if !self.enabled {
+            return None;
+        }
+        let seq = self.sequence();
+        if seq.is_empty() {
+            return None;
+        }
+        let idx = if self.mode == ArpMode::Random {
+            self.chaos.range(seq.len() as u32 - 1) as usize
+        } else {
+            self.pos % seq.len()
+        };
+        self.pos = self.pos.wrapping_add(1);
+
+        let gate_ms = ((self.step_ms as f32 * self.gate) as u32).max(1).min(self.step_ms);
+        Some(ArpStep {
+            note: seq[idx],
+            velocity: self.velocity,
+            gate_ms,
+            rest_ms: self.step_ms - gate_ms,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arp(mode: ArpMode, octaves: u8, notes: &[u8]) -> Arp {
+        let mut arp = Arp::default();
+        arp.set_enabled(true);
+        arp.set_mode(mode);
+        arp.set_octaves(octaves);
+        for n in notes {
+            arp.note_on(*n, 100);
+        }
+        arp
+    }
+
+    fn play(arp: &mut Arp, steps: usize) -> Vec<u8, 32> {
+        (0..steps).filter_map(|_| arp.next_step()).map(|s| s.note).collect()
+    }
+
+    #[test]
+    fn up_over_octaves() {
+        let mut arp = arp(ArpMode::Up, 2, &[64, 60, 67]);
+        assert_eq!(play(&mut arp, 7), [60, 64, 67, 72, 76, 79, 60]);
+    }
+
+    #[test]
+    fn down() {
+        let mut arp = arp(ArpMode::Down, 1, &[64, 60, 67]);
+        assert_eq!(play(&mut arp, 4), [67, 64, 60, 67]);
+    }
+
+    #[test]
+    fn up_down_does_not_repeat_ends() {
+        let mut arp = arp(ArpMode::UpDown, 1, &[60, 64, 67, 71]);
+        assert_eq!(play(&mut arp, 7), [60, 64, 67, 71, 67, 64, 60]);
+    }
+
+    #[test]
+    fn as_played() {
+        let mut arp = arp(ArpMode::AsPlayed, 1, &[67, 60, 64]);
+        assert_eq!(play(&mut arp, 3), [67, 60, 64]);
+    }
+
+    #[test]
+    fn random_stays_in_sequence() {
+        let mut arp = arp(ArpMode::Random, 2, &[60, 64]);
+        for note in play(&mut arp, 32) {
+            assert!([60, 64, 72, 76].contains(&note));
+        }
+    }
+
+    #[test]
+    fn octaves_clip_at_top_note() {
+        let mut arp = arp(ArpMode::Up, 4, &[120]);
+        assert_eq!(play(&mut arp, 2), [120, 120]);
+    }
+
+    #[test]
+    fn release_and_latch() {
+        let mut arp = arp(ArpMode::Up, 1, &[60, 64]);
+        arp.note_off(60);
+        assert_eq!(play(&mut arp, 2), [64, 64]);
+
+        arp.set_latch(true);
+        arp.note_off(64);
+        assert_eq!(play(&mut arp, 1), [64]);
+
+        // new chord after all keys were released replaces latched notes
+        arp.note_on(62, 100);
+        arp.note_off(62);
+        assert_eq!(play(&mut arp, 2), [62, 62]);
+
+        arp.set_latch(false);
+        assert!(arp.next_step().is_none());
+    }
+
+    #[test]
+    fn gate_timing() {
+        let mut arp = arp(ArpMode::Up, 1, &[60]);
+        arp.set_step_ms(200);
+        arp.set_gate(0.25);
+        let step = arp.next_step().unwrap();
+        assert_eq!((step.gate_ms, step.rest_ms), (50, 150));
+    }
+
+    #[test]
+    fn disabled_plays_nothing() {
+        let mut arp = arp(ArpMode::Up, 1, &[60]);
+        arp.set_enabled(false);
+        assert!(arp.next_step().is_none());
+    }
+}
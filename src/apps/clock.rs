@@ -0,0 +1,211 @@
+//! Follows incoming MIDI clock (24 ppqn) to provide tempo and song position
+//! to the apps
+
+use embassy_time::{Duration, Instant};
+use num_enum::FromPrimitive;
+
+/// MIDI clock ticks per quarter note
+pub const PPQN: u32 = 24;
+
+/// Clock is considered stopped if no tick is received for that long (< 10 BPM)
+const TICK_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Musical note durations, expressed in clock ticks
+#[derive(Debug, FromPrimitive, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+#[derive(defmt::Format)]
+pub enum Division {
+    SixteenthTriplet,
+    #[num_enum(default)]
+    Sixteenth,
+    SixteenthDotted,
+    EighthTriplet,
+    Eighth,
+    EighthDotted,
+    QuarterTriplet,
+    Quarter,
+    QuarterDotted,
+    HalfTriplet,
+    Half,
+    HalfDotted,
+    Bar,
+    TwoBars,
+    FourBars,
+}
+
+impl Division {
+    pub const COUNT: u8 = 15;
+
+    pub fn ticks(&self) -> u32 {
+        use Division::*;
+        match self {
+            SixteenthTriplet => PPQN / 6,
+            Sixteenth => PPQN / 4,
+            SixteenthDotted => PPQN * 3 / 8,
+            EighthTriplet => PPQN / 3,
+            Eighth => PPQN / 2,
+            EighthDotted => PPQN * 3 / 4,
+            QuarterTriplet => PPQN * 2 / 3,
+            Quarter => PPQN,
+            QuarterDotted => PPQN * 3 / 2,
+            HalfTriplet => PPQN * 4 / 3,
+            Half => PPQN * 2,
+            HalfDotted => PPQN * 3,
+            Bar => PPQN * 4,
+            TwoBars => PPQN * 8,
+            FourBars => PPQN * 16,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MidiClock {
+    running: bool,
+    // next tick is the first beat after a Start
+    started: bool,
+    // ticks received since last Start
+    ticks: u32,
+    last_tick: Option<Instant>,
+    // smoothed time between ticks
+    tick_ms: f32,
+}
+
+impl Default for MidiClock {
+    fn default() -> Self {
+        Self {
+            running: false,
+            started: false,
+            ticks: 0,
+            last_tick: None,
+            // 120 BPM
+            tick_ms: 60_000.0 / 120.0 / PPQN as f32,
+        }
+    }
+}
+
+impl MidiClock {
+    pub fn tick(&mut self, now: Instant) {
+        if let Some(last) = self.last_tick {
+            let elapsed = now - last;
+            if elapsed < TICK_TIMEOUT {
+                // smooth out jitter from serial and USB transport
+                self.tick_ms = self.tick_ms * 0.75 + elapsed.as_micros() as f32 / 1000.0 * 0.25;
+            }
+        }
+        self.last_tick = Some(now);
+        if self.started {
+            self.ticks = 0;
+            self.started = false;
+        } else {
+            self.ticks = self.ticks.wrapping_add(1);
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.ticks = 0;
+        self.started = true;
+        self.running = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.running = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    /// True if transport is running and ticks are still coming in
+    pub fn is_running(&self, now: Instant) -> bool {
+        self.running && self.last_tick.map(|t| now - t < TICK_TIMEOUT).unwrap_or(false)
+    }
+
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    pub fn get_bpm(&self) -> f32 {
+        60_000.0 / (self.tick_ms * PPQN as f32)
+    }
+
+    /// Ticks since Start, interpolated between received ticks
+    pub fn position(&self, now: Instant) -> f32 {
+        let since_tick = self.last_tick
+            .map(|t| (now - t).as_micros() as f32 / 1000.0)
+            .unwrap_or(0.0);
+        self.ticks as f32 + (since_tick / self.tick_ms).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    #[test]
+    fn division_ticks() {
+        assert_eq!(Division::Quarter.ticks(), 24);
+        assert_eq!(Division::Bar.ticks(), 96);
+        assert_eq!(Division::EighthTriplet.ticks() * 3, Division::Quarter.ticks());
+        assert_eq!(Division::EighthDotted.ticks() * 2, Division::Eighth.ticks() * 3);
+        assert_eq!(Division::from_primitive(Division::COUNT), Division::Sixteenth);
+    }
+
+    #[test]
+    fn first_tick_after_start_is_zero() {
+        let mut clock = MidiClock::default();
+        clock.start();
+        clock.tick(at(1000));
+        assert_eq!(clock.ticks(), 0);
+        clock.tick(at(1020));
+        clock.tick(at(1040));
+        assert_eq!(clock.ticks(), 2);
+        // resume keeps counting
+        clock.stop();
+        clock.resume();
+        clock.tick(at(1060));
+        assert_eq!(clock.ticks(), 3);
+        clock.start();
+        clock.tick(at(1080));
+        assert_eq!(clock.ticks(), 0);
+    }
+
+    #[test]
+    fn bpm_follows_tick_rate() {
+        let mut clock = MidiClock::default();
+        clock.start();
+        // 20ms per tick is 125 BPM
+        for t in 0..100 {
+            clock.tick(at(1000 + t * 20));
+        }
+        assert!((clock.get_bpm() - 125.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn running_until_ticks_stop() {
+        let mut clock = MidiClock::default();
+        assert!(!clock.is_running(at(1000)));
+        clock.start();
+        clock.tick(at(1000));
+        assert!(clock.is_running(at(1100)));
+        assert!(!clock.is_running(at(1300)));
+        clock.tick(at(1300));
+        clock.stop();
+        assert!(!clock.is_running(at(1310)));
+    }
+
+    #[test]
+    fn position_interpolates_up_to_next_tick() {
+        let mut clock = MidiClock::default();
+        clock.start();
+        for t in 0..50 {
+            clock.tick(at(1000 + t * 20));
+        }
+        let last = 1000 + 49 * 20;
+        assert!((clock.position(at(last + 10)) - 49.5).abs() < 0.05);
+        assert_eq!(clock.position(at(last + 100)), 50.0);
+    }
+}
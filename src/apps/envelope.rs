@@ -0,0 +1,247 @@
+//! Software envelope generator (DADSR) triggered by incoming notes
+//! Complements the DW-6000's two fixed EGs as a modulation source
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+/// Max number of notes held at once
+const MAX_HELD: usize = 16;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(defmt::Format)]
+pub enum Stage {
+    Idle,
+    Delay,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Debug)]
+pub struct Envelope {
+    delay_ms: u32,
+    attack_ms: u32,
+    decay_ms: u32,
+    // between 0 and 1
+    sustain: f32,
+    release_ms: u32,
+    stage: Stage,
+    stage_start: Instant,
+    // level when current stage started, used to retrigger and release without clicks
+    start_level: f32,
+    // notes still down, envelope is released when last note goes off
+    held: Vec<u8, MAX_HELD>,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self {
+            delay_ms: 0,
+            attack_ms: 10,
+            decay_ms: 300,
+            sustain: 0.5,
+            release_ms: 300,
+            stage: Stage::Idle,
+            stage_start: Instant::from_ticks(0),
+            start_level: 0.0,
+            held: Vec::new(),
+        }
+    }
+}
+
+impl Envelope {
+    /// Retrigger from current level
+    pub fn note_on(&mut self, note: u8, now: Instant) {
+        if !self.held.contains(&note) {
+            let _ = self.held.push(note);
+        }
+        self.start_stage(Stage::Delay, now);
+    }
+
+    /// Release once all notes are off, notes that were not on are ignored
+    pub fn note_off(&mut self, note: u8, now: Instant) {
+        let Some(idx) = self.held.iter().position(|n| *n == note) else {
+            return;
+        };
+        self.held.swap_remove(idx);
+        if self.held.is_empty() && self.stage != Stage::Idle {
+            self.start_stage(Stage::Release, now);
+        }
+    }
+
+    fn start_stage(&mut self, stage: Stage, now: Instant) {
+        self.start_level = self.value(now);
+        self.stage = stage;
+        self.stage_start = now;
+    }
+
+    /// Move to next stage once current stage time has elapsed
+    fn next_stage(&mut self, stage: Stage, level: f32, after_ms: u32) {
+        self.stage = stage;
+        self.start_level = level;
+        self.stage_start += Duration::from_millis(after_ms as u64);
+    }
+
+    pub fn get_stage(&self) -> Stage {
+        self.stage
+    }
+
+    /// Current envelope output, between 0 and 1
+    pub fn value(&mut self, now: Instant) -> f32 {
+        loop {
+            let elapsed = now.checked_duration_since(self.stage_start)
+                .map(|d| d.as_millis() as u32)
+                .unwrap_or(0);
+            let progress = |stage_ms: u32| elapsed as f32 / stage_ms as f32;
+            match self.stage {
+                Stage::Idle => return 0.0,
+                Stage::Delay if elapsed < self.delay_ms => return self.start_level,
+                Stage::Delay => self.next_stage(Stage::Attack, self.start_level, self.delay_ms),
+                Stage::Attack if elapsed < self.attack_ms =>
+                    return self.start_level + (1.0 - self.start_level) * progress(self.attack_ms),
+                Stage::Attack => self.next_stage(Stage::Decay, 1.0, self.attack_ms),
+                Stage::Decay if elapsed < self.decay_ms =>
+                    return 1.0 + (self.sustain - 1.0) * progress(self.decay_ms),
+                Stage::Decay => self.next_stage(Stage::Sustain, self.sustain, self.decay_ms),
+                Stage::Sustain => return self.sustain,
+                Stage::Release if elapsed < self.release_ms =>
+                    return self.start_level * (1.0 - progress(self.release_ms)),
+                Stage::Release => {
+                    self.stage = Stage::Idle;
+                    return 0.0;
+                }
+            }
+        }
+    }
+
+    pub fn get_delay_ms(&self) -> u32 {
+        self.delay_ms
+    }
+
+    pub fn set_delay_ms(&mut self, delay_ms: u32) {
+        self.delay_ms = delay_ms;
+    }
+
+    pub fn get_attack_ms(&self) -> u32 {
+        self.attack_ms
+    }
+
+    pub fn set_attack_ms(&mut self, attack_ms: u32) {
+        self.attack_ms = attack_ms;
+    }
+
+    pub fn get_decay_ms(&self) -> u32 {
+        self.decay_ms
+    }
+
+    pub fn set_decay_ms(&mut self, decay_ms: u32) {
+        self.decay_ms = decay_ms;
+    }
+
+    pub fn get_sustain(&self) -> f32 {
+        self.sustain
+    }
+
+    pub fn set_sustain(&mut self, sustain: f32) {
+        self.sustain = sustain.max(0.0).min(1.0);
+    }
+
+    pub fn get_release_ms(&self) -> u32 {
+        self.release_ms
+    }
+
+    pub fn set_release_ms(&mut self, release_ms: u32) {
+        self.release_ms = release_ms;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn envelope() -> Envelope {
+        let mut env = Envelope::default();
+        env.set_delay_ms(10);
+        env.set_attack_ms(10);
+        env.set_decay_ms(20);
+        env.set_sustain(0.5);
+        env.set_release_ms(20);
+        env
+    }
+
+    #[test]
+    fn goes_through_stages() {
+        let mut env = envelope();
+        assert_eq!(env.value(at(1000)), 0.0);
+        env.note_on(60, at(1000));
+        assert_eq!(env.value(at(1005)), 0.0);
+        assert_eq!(env.get_stage(), Stage::Delay);
+        assert_eq!(env.value(at(1015)), 0.5);
+        assert_eq!(env.get_stage(), Stage::Attack);
+        assert_eq!(env.value(at(1025)), 0.875);
+        assert_eq!(env.get_stage(), Stage::Decay);
+        assert_eq!(env.value(at(1100)), 0.5);
+        assert_eq!(env.get_stage(), Stage::Sustain);
+        env.note_off(60, at(1100));
+        assert_eq!(env.value(at(1110)), 0.25);
+        assert_eq!(env.get_stage(), Stage::Release);
+        assert_eq!(env.value(at(1120)), 0.0);
+        assert_eq!(env.get_stage(), Stage::Idle);
+    }
+
+    #[test]
+    fn late_read_skips_whole_stages() {
+        let mut env = envelope();
+        env.note_on(60, at(1000));
+        assert_eq!(env.value(at(2000)), 0.5);
+        assert_eq!(env.get_stage(), Stage::Sustain);
+    }
+
+    #[test]
+    fn released_on_last_note_off() {
+        let mut env = envelope();
+        env.note_on(60, at(1000));
+        env.note_on(64, at(1000));
+        env.note_off(60, at(1100));
+        assert_eq!(env.value(at(1100)), 0.5);
+        assert_eq!(env.get_stage(), Stage::Sustain);
+        env.note_off(64, at(1100));
+        assert_eq!(env.get_stage(), Stage::Release);
+    }
+
+    #[test]
+    fn retrigger_starts_from_current_level() {
+        let mut env = envelope();
+        env.note_on(60, at(1000));
+        env.note_off(60, at(1100));
+        env.note_on(60, at(1110));
+        assert_eq!(env.get_stage(), Stage::Delay);
+        assert_eq!(env.value(at(1115)), 0.25);
+        // attack rises from there
+        assert_eq!(env.value(at(1125)), 0.625);
+    }
+
+    #[test]
+    fn repeated_note_on_needs_one_note_off() {
+        let mut env = envelope();
+        env.note_on(60, at(1000));
+        env.note_on(60, at(1000));
+        env.value(at(1100));
+        env.note_off(60, at(1100));
+        assert_eq!(env.get_stage(), Stage::Release);
+    }
+
+    #[test]
+    fn unmatched_note_off_ignored() {
+        let mut env = envelope();
+        env.note_on(60, at(1000));
+        env.value(at(1100));
+        env.note_off(64, at(1100));
+        assert_eq!(env.get_stage(), Stage::Sustain);
+    }
+}
@@ -0,0 +1,218 @@
+//! Undo / redo of parameter edits
+//! Oldest edits are dropped when history is full
+
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, Vec};
+
+use crate::devices::korg::dw6000::Dw6Param;
+
+/// Max number of undo steps kept
+pub const HISTORY_LEN: usize = 32;
+
+/// Moves of a knob closer than this are undone in a single step
+const GROUP_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Max number of params moved by a single gesture (macro legs)
+pub const GROUP_LEN: usize = 8;
+
+/// What made an edit, consecutive moves of the same knob are one undo step
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(defmt::Format)]
+pub enum Gesture {
+    Knob(u8),
+    /// Button or pad press, always a step of its own
+    Press,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[derive(defmt::Format)]
+pub struct Edit {
+    /// Edits sharing a group are undone and redone together
+    pub group: u16,
+    pub param: Dw6Param,
+    pub old: u8,
+    pub new: u8,
+}
+
+#[derive(Debug)]
+pub struct History {
+    undo: Deque<Edit, HISTORY_LEN>,
+    redo: Vec<Edit, HISTORY_LEN>,
+    last_edit: Option<(Gesture, Instant)>,
+    // group of the latest edit
+    group: u16,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: Deque::new(),
+            redo: Vec::new(),
+            last_edit: None,
+            group: 0,
+        }
+    }
+}
+
+impl History {
+    pub fn record(&mut self, gesture: Gesture, param: Dw6Param, old: u8, new: u8, now: Instant) {
+        if old == new {
+            return;
+        }
+        self.redo.clear();
+        let same_gesture = match self.last_edit {
+            Some((Gesture::Knob(knob), last)) => gesture == Gesture::Knob(knob) && now - last < GROUP_TIMEOUT,
+            _ => false,
+        };
+        self.last_edit = Some((gesture, now));
+        if !same_gesture {
+            self.group = self.group.wrapping_add(1);
+        }
+        // each param moved by the gesture keeps a single entry
+        let group = self.group;
+        if let Some(same) = self.undo.iter_mut().rev().take_while(|e| e.group == group).find(|e| e.param == param) {
+            same.new = new;
+            return;
+        }
+        if self.undo.is_full() {
+            self.undo.pop_front();
+        }
+        let _ = self.undo.push_back(Edit { group, param, old, new });
+    }
+
+    /// Edits to revert, latest first, their `old` value should be applied
+    pub fn undo(&mut self) -> Vec<Edit, GROUP_LEN> {
+        let mut edits = Vec::new();
+        let Some(group) = self.undo.back().map(|e| e.group) else {
+            return edits;
+        };
+        while self.undo.back().map(|e| e.group) == Some(group) && !edits.is_full() {
+            if let Some(edit) = self.undo.pop_back() {
+                let _ = self.redo.push(edit);
+                let _ = edits.push(edit);
+            }
+        }
+        self.last_edit = None;
+        edits
+    }
+
+    /// Edits to replay, oldest first, their `new` value should be applied
+    pub fn redo(&mut self) -> Vec<Edit, GROUP_LEN> {
+        let mut edits = Vec::new();
+        let Some(group) = self.redo.last().map(|e| e.group) else {
+            return edits;
+        };
+        while self.redo.last().map(|e| e.group) == Some(group) && !edits.is_full() {
+            if let Some(edit) = self.redo.pop() {
+                let _ = self.undo.push_back(edit);
+                let _ = edits.push(edit);
+            }
+        }
+        self.last_edit = None;
+        edits
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.last_edit = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn values(edits: Vec<Edit, GROUP_LEN>) -> std::vec::Vec<(Dw6Param, u8, u8)> {
+        edits.iter().map(|e| (e.param, e.old, e.new)).collect()
+    }
+
+    const KNOB: Gesture = Gesture::Knob(1);
+
+    #[test]
+    fn undo_then_redo() {
+        let mut history = History::default();
+        history.record(KNOB, Dw6Param::Cutoff, 10, 20, at(0));
+        history.record(Gesture::Knob(2), Dw6Param::Resonance, 1, 2, at(100));
+        assert_eq!(values(history.undo()), [(Dw6Param::Resonance, 1, 2)]);
+        assert_eq!(values(history.undo()), [(Dw6Param::Cutoff, 10, 20)]);
+        assert!(history.undo().is_empty());
+        assert_eq!(values(history.redo()), [(Dw6Param::Cutoff, 10, 20)]);
+        assert_eq!(values(history.redo()), [(Dw6Param::Resonance, 1, 2)]);
+        assert!(history.redo().is_empty());
+    }
+
+    #[test]
+    fn quick_moves_grouped() {
+        let mut history = History::default();
+        history.record(KNOB, Dw6Param::Cutoff, 10, 11, at(0));
+        history.record(KNOB, Dw6Param::Cutoff, 11, 12, at(500));
+        history.record(KNOB, Dw6Param::Cutoff, 12, 13, at(1000));
+        // too late to group
+        history.record(KNOB, Dw6Param::Cutoff, 13, 14, at(3000));
+        assert_eq!(values(history.undo()), [(Dw6Param::Cutoff, 13, 14)]);
+        assert_eq!(values(history.undo()), [(Dw6Param::Cutoff, 10, 13)]);
+    }
+
+    #[test]
+    fn gesture_moving_several_params_one_step() {
+        let mut history = History::default();
+        history.record(KNOB, Dw6Param::Cutoff, 10, 11, at(0));
+        history.record(KNOB, Dw6Param::VcfInt, 20, 19, at(0));
+        history.record(KNOB, Dw6Param::Cutoff, 11, 12, at(500));
+        history.record(KNOB, Dw6Param::VcfInt, 19, 18, at(500));
+        assert_eq!(values(history.undo()), [(Dw6Param::VcfInt, 20, 18), (Dw6Param::Cutoff, 10, 12)]);
+        assert!(history.undo().is_empty());
+        assert_eq!(values(history.redo()), [(Dw6Param::Cutoff, 10, 12), (Dw6Param::VcfInt, 20, 18)]);
+    }
+
+    #[test]
+    fn other_gestures_not_grouped() {
+        let mut history = History::default();
+        history.record(KNOB, Dw6Param::Cutoff, 10, 11, at(0));
+        history.record(Gesture::Knob(2), Dw6Param::Cutoff, 11, 12, at(100));
+        history.record(Gesture::Press, Dw6Param::Chorus, 0, 1, at(200));
+        history.record(Gesture::Press, Dw6Param::Chorus, 1, 0, at(300));
+        assert_eq!(values(history.undo()), [(Dw6Param::Chorus, 1, 0)]);
+        assert_eq!(values(history.undo()), [(Dw6Param::Chorus, 0, 1)]);
+        assert_eq!(values(history.undo()), [(Dw6Param::Cutoff, 11, 12)]);
+        assert_eq!(values(history.undo()), [(Dw6Param::Cutoff, 10, 11)]);
+    }
+
+    #[test]
+    fn no_change_not_recorded() {
+        let mut history = History::default();
+        history.record(KNOB, Dw6Param::Cutoff, 10, 10, at(0));
+        assert!(history.undo().is_empty());
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut history = History::default();
+        history.record(KNOB, Dw6Param::Cutoff, 10, 20, at(0));
+        history.undo();
+        history.record(KNOB, Dw6Param::Resonance, 1, 2, at(100));
+        assert!(history.redo().is_empty());
+    }
+
+    #[test]
+    fn oldest_dropped_when_full() {
+        let mut history = History::default();
+        for i in 0..=HISTORY_LEN as u8 {
+            history.record(KNOB, Dw6Param::Cutoff, i, i + 1, at(i as u64 * 2000));
+        }
+        for _ in 0..HISTORY_LEN {
+            assert!(!history.undo().is_empty());
+        }
+        // first edit was dropped
+        assert!(history.undo().is_empty());
+        for _ in 0..HISTORY_LEN {
+            assert!(!history.redo().is_empty());
+        }
+        assert!(history.redo().is_empty());
+    }
+}
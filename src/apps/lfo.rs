@@ -5,6 +5,9 @@ use micromath::F32Ext;
 use core::fmt::{Debug, Formatter};
 use embassy_time::Instant;
 
+use crate::apps::clock::{Division, MidiClock};
+use crate::chaos::WyRand;
+
 #[derive(Debug, FromPrimitive, Copy, Clone)]
 #[repr(u8)]
 pub enum Waveform {
@@ -14,7 +17,14 @@ pub enum Waveform {
     Saw,
     RevSaw,
     Square,
-    // Random,
+    /// Sample and hold, new random level every cycle
+    Random,
+    /// Glides from one random level to the next over each cycle
+    SmoothRandom,
+}
+
+impl Waveform {
+    pub const COUNT: u8 = 7;
 }
 
 impl Default for Waveform {
@@ -36,6 +46,13 @@ pub struct Lfo {
     // between 0 and 1
     amount: f32,
     wave: Waveform,
+    // follow MIDI clock instead of free running period
+    sync: Option<Division>,
+    chaos: WyRand,
+    // random levels for current cycle, between -1 and 1
+    prev_level: f32,
+    next_level: f32,
+    cycle: u32,
 }
 
 impl Default for Lfo {
@@ -43,46 +60,69 @@ impl Default for Lfo {
         Self {
             offset: Instant::now(),
             period: 200.0,
-            amount: 0.0,
+            amount: 1.0,
             wave: Default::default(),
+            sync: None,
+            chaos: WyRand::default(),
+            prev_level: 0.0,
+            next_level: 0.0,
+            cycle: 0,
         }
     }
 }
 
 // Yes, these computations are HORRIBLY INEFFICIENT and naive. IJDGAF.
 impl Lfo {
-    pub fn mod_value(&mut self, froot: f32/*, chaos: &mut WyRand*/) -> f32 {
-        let now = Instant::now();
-        let time = (now - self.offset).as_millis() as f32;
-        (froot + match self.wave {
+    /// Current LFO output, between -amount and +amount
+    pub fn value(&mut self, clock: &MidiClock) -> f32 {
+        let cycles = self.cycles(Instant::now(), clock);
+        self.wave_value(cycles) * self.amount
+    }
+
+    /// Waveform output at a position, in cycles since start
+    fn wave_value(&mut self, cycles: f32) -> f32 {
+        let phase = cycles.fract();
+        let cycle = cycles as u32;
+        if cycle != self.cycle {
+            // only move one step even if many cycles were skipped, keeps things smooth
+            self.cycle = cycle;
+            self.prev_level = self.next_level;
+            self.next_level = self.chaos.bipolar();
+        }
+        match self.wave {
             Waveform::Triangle => {
-                let timex = time % self.period;
-                let half = self.period / 2.0;
-                let mut modulation = timex / half;
-                if timex > half {
-                    modulation = 1.0 - modulation;
+                if phase < 0.5 {
+                    phase * 4.0 - 1.0
+                } else {
+                    3.0 - phase * 4.0
                 }
-                (modulation - 0.5) * 2.0 * self.amount
-            }
-            Waveform::Sine => {
-                let timex = time / self.period;
-                timex.sin() * self.amount
-            }
-            Waveform::Square => {
-                let timex = time % self.period;
-                let half = self.period / 2.0;
-                (if timex > half { 1.0 } else { -1.0 }) * self.amount
-            }
-            Waveform::Saw => {
-                let timex = time / self.period;
-                ((1.0 - timex.fract()) - 0.5) * 2.0 * self.amount
-            }
-            Waveform::RevSaw => {
-                let timex = time / self.period;
-                (timex.fract() - 0.5) * 2.0 * self.amount
             }
-            // Waveform::Random => ((chaos.generate_range::<u32>(0, u32::MAX) as f32 / u32::MAX as f32) - 0.5) * 2.0 * self.amount
-        }).max(0.0).min(1.0)
+            Waveform::Sine => (phase * 2.0 * f32::consts::PI).sin(),
+            Waveform::Square => if phase > 0.5 { 1.0 } else { -1.0 },
+            Waveform::Saw => (1.0 - phase - 0.5) * 2.0,
+            Waveform::RevSaw => (phase - 0.5) * 2.0,
+            Waveform::Random => self.next_level,
+            Waveform::SmoothRandom => self.prev_level + (self.next_level - self.prev_level) * phase,
+        }
+    }
+
+    /// Cycles elapsed since start
+    /// Synced LFO falls back to free running when clock is not running
+    fn cycles(&self, now: Instant, clock: &MidiClock) -> f32 {
+        match self.sync {
+            Some(div) if clock.is_running(now) => clock.position(now) / div.ticks() as f32,
+            _ => (now - self.offset).as_millis() as f32 / self.period,
+        }
+    }
+
+    /// Restart random sequence from a new seed
+    pub fn seed(&mut self, seed: u64) {
+        self.chaos = WyRand::new(seed);
+    }
+
+    /// Restart cycle from the beginning
+    pub fn reset(&mut self) {
+        self.offset = Instant::now();
     }
 
     pub fn get_amount(&self) -> f32 {
@@ -109,4 +149,70 @@ impl Lfo {
     pub fn set_waveform(&mut self, wave: Waveform) {
         self.wave = wave;
     }
-}
\ No newline at end of file
+
+    pub fn get_sync(&self) -> Option<Division> {
+        self.sync
+    }
+
+    pub fn set_sync(&mut self, sync: Option<Division>) {
+        self.sync = sync;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lfo(wave: Waveform, seed: u64) -> Lfo {
+        let mut lfo = Lfo::default();
+        lfo.set_waveform(wave);
+        lfo.seed(seed);
+        lfo
+    }
+
+    fn levels(lfo: &mut Lfo) -> [f32; 8] {
+        let mut levels = [0.0; 8];
+        for (i, level) in levels.iter_mut().enumerate() {
+            *level = lfo.wave_value(i as f32 + 1.5);
+        }
+        levels
+    }
+
+    #[test]
+    fn same_seed_same_output() {
+        let a = levels(&mut lfo(Waveform::Random, 1234));
+        let b = levels(&mut lfo(Waveform::Random, 1234));
+        let c = levels(&mut lfo(Waveform::Random, 4321));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn random_holds_over_cycle() {
+        let mut lfo = lfo(Waveform::Random, 1234);
+        let held = lfo.wave_value(3.1);
+        assert_eq!(lfo.wave_value(3.5), held);
+        assert_eq!(lfo.wave_value(3.9), held);
+        assert_ne!(lfo.wave_value(4.1), held);
+    }
+
+    #[test]
+    fn smooth_random_is_continuous() {
+        let mut lfo = lfo(Waveform::SmoothRandom, 1234);
+        lfo.wave_value(1.0);
+        let end = lfo.wave_value(1.999);
+        let start = lfo.wave_value(2.0);
+        assert!((end - start).abs() < 0.01);
+    }
+
+    #[test]
+    fn random_in_range() {
+        for wave in [Waveform::Random, Waveform::SmoothRandom] {
+            let mut lfo = lfo(wave, 99);
+            for i in 0..1000 {
+                let v = lfo.wave_value(i as f32 * 0.3);
+                assert!((-1.0..=1.0).contains(&v));
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Macro knobs, moving several DW-6000 params at once
+//! Each param goes along its own range, in either direction
+
+use crate::devices::korg::dw6000::Dw6Param;
+use crate::scale::interpolate;
+
+/// Macro knob position goes from 0 (all params at `from`) to this (all params at `to`)
+pub const MACRO_MAX: u8 = 127;
+
+#[derive(Debug, Copy, Clone)]
+#[derive(defmt::Format)]
+pub struct MacroParam {
+    pub param: Dw6Param,
+    /// Value with knob fully down
+    pub from: u8,
+    /// Value with knob fully up, may be lower than `from`
+    pub to: u8,
+}
+
+impl MacroParam {
+    /// Param value at knob position
+    pub fn value(&self, position: u8) -> u8 {
+        interpolate(self.from, self.to, position, MACRO_MAX).min(self.param.max_value())
+    }
+}
+
+/// Params moved by a macro knob
+pub type Macro = &'static [MacroParam];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints() {
+        let leg = MacroParam { param: Dw6Param::Cutoff, from: 12, to: 63 };
+        assert_eq!(leg.value(0), 12);
+        assert_eq!(leg.value(MACRO_MAX), 63);
+        assert_eq!(leg.value(64), 38);
+    }
+
+    #[test]
+    fn goes_down_when_from_above_to() {
+        let leg = MacroParam { param: Dw6Param::VcfInt, from: 20, to: 4 };
+        assert_eq!(leg.value(0), 20);
+        assert_eq!(leg.value(MACRO_MAX), 4);
+        assert!(leg.value(32) > leg.value(96));
+    }
+
+    #[test]
+    fn clamped_to_param_range() {
+        // VcaAttack goes up to 31
+        let leg = MacroParam { param: Dw6Param::VcaAttack, from: 0, to: 40 };
+        assert_eq!(leg.value(MACRO_MAX), Dw6Param::VcaAttack.max_value());
+        // past the end of knob travel
+        let leg = MacroParam { param: Dw6Param::Cutoff, from: 0, to: 63 };
+        assert_eq!(leg.value(255), 63);
+    }
+}
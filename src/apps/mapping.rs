@@ -0,0 +1,100 @@
+//! Knob assignments, per page and CC number
+//! Loaded from a default table on startup, can be changed at runtime with MIDI learn
+
+use core::hash::Hash;
+
+use hashbrown::HashMap;
+
+use crate::scale::Scale;
+
+/// What a knob controls, and how its travel maps onto the target's values
+#[derive(Debug, Copy, Clone)]
+#[derive(defmt::Format)]
+pub struct Assign<T> {
+    pub target: T,
+    pub scale: Scale,
+}
+
+#[derive(Debug)]
+pub struct Mapping<P, T> {
+    assigns: HashMap<(P, u8), Assign<T>>,
+}
+
+impl<P, T> Default for Mapping<P, T> {
+    fn default() -> Self {
+        Self {
+            assigns: HashMap::new(),
+        }
+    }
+}
+
+impl<P: Copy + Eq + Hash, T: Copy + PartialEq> Mapping<P, T> {
+    pub fn get(&self, page: P, cc: u8) -> Option<Assign<T>> {
+        self.assigns.get(&(page, cc)).copied()
+    }
+
+    /// Assign knob sending cc on page, or leave it unassigned
+    pub fn set(&mut self, page: P, cc: u8, assign: Option<Assign<T>>) {
+        match assign {
+            Some(assign) => {
+                self.assigns.insert((page, cc), assign);
+            }
+            None => {
+                self.assigns.remove(&(page, cc));
+            }
+        }
+    }
+
+    /// Lowest CC controlling target on page, if any
+    pub fn find(&self, page: P, target: T) -> Option<u8> {
+        self.assigns.iter()
+            .filter(|((p, _), assign)| *p == page && assign.target == target)
+            .map(|((_, cc), _)| *cc)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scale::Curve;
+
+    fn assign(target: u8) -> Option<Assign<u8>> {
+        Some(Assign { target, scale: Scale::new(127, Curve::Linear) })
+    }
+
+    #[test]
+    fn set_then_get() {
+        let mut mapping: Mapping<u8, u8> = Mapping::default();
+        assert!(mapping.get(0, 1).is_none());
+        mapping.set(0, 1, assign(10));
+        assert_eq!(mapping.get(0, 1).map(|a| a.target), Some(10));
+        assert!(mapping.get(1, 1).is_none());
+        mapping.set(0, 1, assign(11));
+        assert_eq!(mapping.get(0, 1).map(|a| a.target), Some(11));
+    }
+
+    #[test]
+    fn find_lowest_cc_on_page() {
+        let mut mapping: Mapping<u8, u8> = Mapping::default();
+        mapping.set(0, 9, assign(10));
+        mapping.set(0, 3, assign(10));
+        mapping.set(1, 1, assign(10));
+        assert_eq!(mapping.find(0, 10), Some(3));
+        assert_eq!(mapping.find(1, 10), Some(1));
+        assert_eq!(mapping.find(0, 11), None);
+    }
+
+    #[test]
+    fn unassign() {
+        let mut mapping: Mapping<u8, u8> = Mapping::default();
+        mapping.set(0, 3, assign(10));
+        mapping.set(0, 9, assign(10));
+        mapping.set(0, 3, None);
+        assert!(mapping.get(0, 3).is_none());
+        assert_eq!(mapping.find(0, 10), Some(9));
+        // unassigning a free knob does nothing
+        mapping.set(0, 4, None);
+        assert_eq!(mapping.find(0, 10), Some(9));
+    }
+}
@@ -1,5 +1,18 @@
-pub mod dw6_control;
-pub mod blinky_beat;
 pub mod lfo;
+pub mod arp;
+pub mod clock;
+pub mod mod_matrix;
+pub mod envelope;
+pub mod takeover;
+pub mod param_queue;
+pub mod history;
+pub mod morph;
+pub mod randomizer;
+pub mod mapping;
+pub mod macro_knob;
+pub mod motion;
+pub mod velocity;
+pub mod note_play;
+pub mod note_tracker;
 // pub mod bounce;
 
@@ -0,0 +1,147 @@
+//! Routes modulation sources to DW-6000 parameters
+//! Each route adds its source value, scaled by a signed depth, around the parameter's root value
+
+use embassy_time::Instant;
+use num_enum::FromPrimitive;
+
+use crate::apps::clock::MidiClock;
+use crate::apps::envelope::Envelope;
+use crate::apps::lfo::Lfo;
+use crate::devices::korg::dw6000::Dw6Param;
+
+pub const LFO_COUNT: usize = 2;
+
+pub const MAX_ROUTES: usize = 8;
+
+#[derive(Debug, FromPrimitive, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+#[derive(defmt::Format)]
+pub enum ModSource {
+    #[num_enum(default)]
+    Lfo1,
+    Lfo2,
+    Velocity,
+    ModWheel,
+    Aftertouch,
+    Env,
+}
+
+impl ModSource {
+    pub const COUNT: u8 = 6;
+
+    /// Index of the LFO this source reads, if it is one
+    pub fn lfo(&self) -> Option<usize> {
+        match self {
+            ModSource::Lfo1 => Some(0),
+            ModSource::Lfo2 => Some(1),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[derive(defmt::Format)]
+pub struct ModRoute {
+    pub source: ModSource,
+    pub dest: Dw6Param,
+    // between -1 and 1
+    pub depth: f32,
+}
+
+#[derive(Debug)]
+pub struct ModMatrix {
+    lfos: [Lfo; LFO_COUNT],
+    env: Envelope,
+    routes: [Option<ModRoute>; MAX_ROUTES],
+    // last received controller values, between 0 and 1
+    velocity: f32,
+    mod_wheel: f32,
+    aftertouch: f32,
+}
+
+impl Default for ModMatrix {
+    fn default() -> Self {
+        Self {
+            lfos: [Lfo::default(), Lfo::default()],
+            env: Envelope::default(),
+            routes: [None; MAX_ROUTES],
+            velocity: 0.0,
+            mod_wheel: 0.0,
+            aftertouch: 0.0,
+        }
+    }
+}
+
+impl ModMatrix {
+    pub fn lfo_mut(&mut self, idx: usize) -> &mut Lfo {
+        &mut self.lfos[idx]
+    }
+
+    pub fn env_mut(&mut self) -> &mut Envelope {
+        &mut self.env
+    }
+
+    /// Give each LFO its own random sequence
+    pub fn seed(&mut self, seed: u64) {
+        for (idx, lfo) in self.lfos.iter_mut().enumerate() {
+            lfo.seed(seed.wrapping_add(idx as u64));
+        }
+    }
+
+    pub fn reset_lfos(&mut self) {
+        for lfo in &mut self.lfos {
+            lfo.reset()
+        }
+    }
+
+    pub fn get_route(&self, slot: usize) -> Option<ModRoute> {
+        self.routes[slot]
+    }
+
+    /// Returns the route previously in that slot
+    pub fn set_route(&mut self, slot: usize, route: Option<ModRoute>) -> Option<ModRoute> {
+        core::mem::replace(&mut self.routes[slot], route)
+    }
+
+    pub fn is_routed(&self, dest: Dw6Param) -> bool {
+        self.routes.iter().flatten().any(|r| r.dest == dest)
+    }
+
+    pub fn routed(&self) -> impl Iterator<Item=Dw6Param> + '_ {
+        self.routes.iter().flatten().map(|r| r.dest)
+    }
+
+    /// Update a controller source from its 7-bit MIDI value
+    /// LFO and envelope sources are computed and can not be set
+    pub fn set_input(&mut self, source: ModSource, value: u8) {
+        let value = value as f32 / 127.0;
+        match source {
+            ModSource::Velocity => self.velocity = value,
+            ModSource::ModWheel => self.mod_wheel = value,
+            ModSource::Aftertouch => self.aftertouch = value,
+            ModSource::Lfo1 | ModSource::Lfo2 | ModSource::Env => {}
+        }
+    }
+
+    fn source_value(&mut self, source: ModSource, clock: &MidiClock) -> f32 {
+        match source {
+            ModSource::Lfo1 => self.lfos[0].value(clock),
+            ModSource::Lfo2 => self.lfos[1].value(clock),
+            ModSource::Velocity => self.velocity,
+            ModSource::ModWheel => self.mod_wheel,
+            ModSource::Aftertouch => self.aftertouch,
+            ModSource::Env => self.env.value(Instant::now()),
+        }
+    }
+
+    /// Sum all routes to that param around its root value
+    pub fn modulate(&mut self, param: Dw6Param, root: u8, clock: &MidiClock) -> u8 {
+        let fmax = param.max_value() as f32;
+        let mut fvalue = root as f32 / fmax;
+        let routes = self.routes;
+        for route in routes.iter().flatten().filter(|r| r.dest == param) {
+            fvalue += route.depth * self.source_value(route.source, clock);
+        }
+        (fvalue.max(0.0).min(1.0) * fmax + 0.5) as u8
+    }
+}
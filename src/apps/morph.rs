@@ -0,0 +1,98 @@
+//! Morphs between two DW-6000 patches
+//! Continuous params are interpolated, stepped params (waveforms, octaves, switches) flip at midpoint
+
+use heapless::Vec;
+
+use crate::devices::korg::dw6000::{self, Dw6Param};
+use crate::scale::{interpolate, Curve};
+
+/// Morph position goes from 0 (patch A) to this (patch B)
+pub const MORPH_MAX: u8 = 127;
+
+#[derive(Debug)]
+pub struct Morph<const N: usize> {
+    a: Vec<u8, N>,
+    b: Vec<u8, N>,
+    position: u8,
+}
+
+impl<const N: usize> Morph<N> {
+    pub fn new(a: Vec<u8, N>, b: Vec<u8, N>) -> Self {
+        Self { a, b, position: 0 }
+    }
+
+    pub fn get_position(&self) -> u8 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: u8) {
+        self.position = position.min(MORPH_MAX)
+    }
+
+    /// Move position by a relative encoder step
+    pub fn step(&mut self, step: i8) {
+        self.set_position((self.position as i16 + step as i16).max(0) as u8)
+    }
+
+    /// Param value at current position
+    pub fn value(&self, param: Dw6Param) -> u8 {
+        let a = dw6000::get_param_value(param, &self.a);
+        let b = dw6000::get_param_value(param, &self.b);
+        match param.scale().curve {
+            Curve::Stepped => if self.position < MORPH_MAX / 2 + 1 { a } else { b },
+            _ => interpolate(a, b, self.position, MORPH_MAX),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(cutoff: u8, wave: u8) -> Vec<u8, 26> {
+        let mut patch = Vec::from_slice(&[0; 26]).unwrap();
+        dw6000::set_param_value(Dw6Param::Cutoff, cutoff, &mut patch);
+        dw6000::set_param_value(Dw6Param::Osc1Wave, wave, &mut patch);
+        patch
+    }
+
+    #[test]
+    fn endpoints_are_snapshots() {
+        let mut morph = Morph::new(patch(10, 0), patch(50, 3));
+        assert_eq!(morph.value(Dw6Param::Cutoff), 10);
+        assert_eq!(morph.value(Dw6Param::Osc1Wave), 0);
+        morph.set_position(MORPH_MAX);
+        assert_eq!(morph.value(Dw6Param::Cutoff), 50);
+        assert_eq!(morph.value(Dw6Param::Osc1Wave), 3);
+    }
+
+    #[test]
+    fn interpolates_both_ways() {
+        let mut morph = Morph::new(patch(10, 0), patch(50, 3));
+        morph.set_position(64);
+        assert_eq!(morph.value(Dw6Param::Cutoff), 30);
+        let mut morph = Morph::new(patch(50, 0), patch(10, 3));
+        morph.set_position(64);
+        assert_eq!(morph.value(Dw6Param::Cutoff), 30);
+    }
+
+    #[test]
+    fn stepped_flips_at_midpoint() {
+        let mut morph = Morph::new(patch(10, 0), patch(50, 3));
+        morph.set_position(MORPH_MAX / 2);
+        assert_eq!(morph.value(Dw6Param::Osc1Wave), 0);
+        morph.set_position(MORPH_MAX / 2 + 1);
+        assert_eq!(morph.value(Dw6Param::Osc1Wave), 3);
+    }
+
+    #[test]
+    fn position_clamped() {
+        let mut morph = Morph::new(patch(10, 0), patch(50, 3));
+        morph.step(-5);
+        assert_eq!(morph.get_position(), 0);
+        morph.set_position(200);
+        assert_eq!(morph.get_position(), MORPH_MAX);
+        morph.step(5);
+        assert_eq!(morph.value(Dw6Param::Cutoff), 50);
+    }
+}
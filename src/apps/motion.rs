@@ -0,0 +1,299 @@
+//! Records knob moves on DW-6000 params and loops them back in time with the clock
+//! Positions are plain clock ticks (no embassy types) so the recorder can be exercised on the host.
+
+use heapless::Vec;
+
+use crate::apps::clock::Division;
+use crate::devices::korg::dw6000::Dw6Param;
+
+/// Max number of moves in the loop, new moves are dropped when full
+const MAX_EVENTS: usize = 512;
+
+/// Recorded positions per clock tick
+const TICK_STEPS: f32 = 4.0;
+
+/// Params played back at once, one value each
+const MAX_PARAMS: usize = 35;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(defmt::Format)]
+struct MotionEvent {
+    // loop position, in ticks * TICK_STEPS
+    pos: u16,
+    param: Dw6Param,
+    value: u8,
+    // recorded during current pass, not played back until next pass
+    fresh: bool,
+}
+
+#[derive(Debug)]
+pub struct Motion {
+    // ordered by position
+    events: Vec<MotionEvent, MAX_EVENTS>,
+    length: Division,
+    recording: bool,
+    playing: bool,
+    // one bit per param, in Dw6Param order
+    mutes: u64,
+    // params moved during current pass while recording, their older moves are erased as the loop goes by
+    touched: u64,
+    // loop position of last advance
+    cursor: Option<u16>,
+}
+
+impl Default for Motion {
+    fn default() -> Self {
+        Self {
+            events: Vec::new(),
+            length: Division::TwoBars,
+            recording: false,
+            playing: false,
+            mutes: 0,
+            touched: 0,
+            cursor: None,
+        }
+    }
+}
+
+impl Motion {
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Recording also starts playback, to overdub over existing moves
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recording = recording;
+        if recording {
+            self.set_playing(true);
+        } else {
+            self.end_pass();
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Stopping playback also stops recording
+    pub fn set_playing(&mut self, playing: bool) {
+        if !playing {
+            self.recording = false;
+            self.end_pass();
+        }
+        self.playing = playing;
+        self.cursor = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Erase all recorded moves
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.touched = 0;
+    }
+
+    pub fn get_length(&self) -> Division {
+        self.length
+    }
+
+    /// Moves past the end of a shorter loop are erased
+    pub fn set_length(&mut self, length: Division) {
+        self.length = length;
+        let end = self.end();
+        self.events.retain(|e| (e.pos as u32) < end);
+        self.cursor = None;
+    }
+
+    pub fn is_muted(&self, param: Dw6Param) -> bool {
+        self.mutes & (1 << param as u64) != 0
+    }
+
+    /// Muted params keep their moves but are not played back
+    pub fn set_muted(&mut self, param: Dw6Param, muted: bool) {
+        if muted {
+            self.mutes |= 1 << param as u64;
+        } else {
+            self.mutes &= !(1 << param as u64);
+        }
+    }
+
+    fn end(&self) -> u32 {
+        self.length.ticks() * TICK_STEPS as u32
+    }
+
+    fn loop_pos(&self, ticks: f32) -> u16 {
+        ((ticks.max(0.0) * TICK_STEPS) as u32 % self.end()) as u16
+    }
+
+    fn is_touched(&self, param: Dw6Param) -> bool {
+        self.touched & (1 << param as u64) != 0
+    }
+
+    fn end_pass(&mut self) {
+        self.touched = 0;
+        for e in self.events.iter_mut() {
+            e.fresh = false;
+        }
+    }
+
+    /// Record param move at clock position, if recording
+    /// Returns false if the move was dropped because the loop is full
+    pub fn record(&mut self, ticks: f32, param: Dw6Param, value: u8) -> bool {
+        if !self.recording {
+            return true;
+        }
+        self.touched |= 1 << param as u64;
+        let pos = self.loop_pos(ticks);
+        let event = MotionEvent { pos, param, value, fresh: true };
+        if let Some(same) = self.events.iter_mut().find(|e| e.pos == pos && e.param == param) {
+            *same = event;
+            return true;
+        }
+        let idx = self.events.iter().position(|e| e.pos > pos).unwrap_or(self.events.len());
+        self.events.insert(idx, event).is_ok()
+    }
+
+    /// Move playback to clock position
+    /// Returns the latest value of each param moved since last advance
+    pub fn advance(&mut self, ticks: f32) -> Vec<(Dw6Param, u8), MAX_PARAMS> {
+        let mut values = Vec::new();
+        if !self.playing {
+            return values;
+        }
+        let pos = self.loop_pos(ticks);
+        let Some(cursor) = self.cursor.replace(pos) else {
+            return values;
+        };
+        if pos == cursor {
+            return values;
+        }
+        // loop went around
+        let wrapped = pos < cursor;
+        let passed = |p: u16| if wrapped { p > cursor || p <= pos } else { p > cursor && p <= pos };
+
+        let mut idx = 0;
+        while idx < self.events.len() {
+            let event = self.events[idx];
+            if passed(event.pos) && !event.fresh {
+                if self.recording && self.is_touched(event.param) {
+                    // overdubbed
+                    self.events.remove(idx);
+                    continue;
+                }
+                if !self.is_muted(event.param) {
+                    if let Some(value) = values.iter_mut().find(|(p, _)| *p == event.param) {
+                        value.1 = event.value;
+                    } else {
+                        let _ = values.push((event.param, event.value));
+                    }
+                }
+            }
+            idx += 1;
+        }
+        if wrapped {
+            self.end_pass();
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two bars
+    const LOOP: f32 = 192.0;
+
+    #[test]
+    fn plays_back_recorded_moves() {
+        let mut motion = Motion::default();
+        motion.set_recording(true);
+        motion.advance(0.0);
+        assert!(motion.record(10.0, Dw6Param::Cutoff, 20));
+        assert!(motion.record(20.0, Dw6Param::Cutoff, 40));
+        motion.advance(30.0);
+        motion.set_recording(false);
+
+        motion.advance(LOOP + 5.0);
+        assert_eq!(motion.advance(LOOP + 15.0), [(Dw6Param::Cutoff, 20)]);
+        assert_eq!(motion.advance(LOOP + 25.0), [(Dw6Param::Cutoff, 40)]);
+        assert!(motion.advance(LOOP + 100.0).is_empty());
+    }
+
+    #[test]
+    fn fresh_moves_not_played_back_in_same_pass() {
+        let mut motion = Motion::default();
+        motion.set_recording(true);
+        motion.advance(0.0);
+        motion.record(10.0, Dw6Param::Cutoff, 20);
+        assert!(motion.advance(11.0).is_empty());
+        // next pass
+        motion.advance(LOOP - 1.0);
+        motion.advance(LOOP + 1.0);
+        motion.set_recording(false);
+        assert_eq!(motion.advance(LOOP + 11.0), [(Dw6Param::Cutoff, 20)]);
+    }
+
+    #[test]
+    fn latest_value_per_param() {
+        let mut motion = Motion::default();
+        motion.set_recording(true);
+        motion.advance(0.0);
+        motion.record(1.0, Dw6Param::Cutoff, 1);
+        motion.record(2.0, Dw6Param::Resonance, 2);
+        motion.record(3.0, Dw6Param::Cutoff, 3);
+        motion.set_recording(false);
+        motion.advance(0.0);
+        assert_eq!(motion.advance(10.0), [(Dw6Param::Cutoff, 3), (Dw6Param::Resonance, 2)]);
+    }
+
+    #[test]
+    fn overdub_replaces_touched_params_only() {
+        let mut motion = Motion::default();
+        motion.set_recording(true);
+        motion.advance(0.0);
+        motion.record(10.0, Dw6Param::Cutoff, 20);
+        motion.record(10.0, Dw6Param::Resonance, 5);
+        motion.set_recording(false);
+
+        // overdub cutoff on next pass
+        motion.set_recording(true);
+        motion.advance(LOOP + 1.0);
+        motion.record(LOOP + 2.0, Dw6Param::Cutoff, 50);
+        assert_eq!(motion.advance(LOOP + 20.0), [(Dw6Param::Resonance, 5)]);
+        motion.set_recording(false);
+
+        motion.advance(2.0 * LOOP);
+        assert_eq!(motion.advance(2.0 * LOOP + 20.0), [(Dw6Param::Cutoff, 50), (Dw6Param::Resonance, 5)]);
+    }
+
+    #[test]
+    fn muted_params_skipped() {
+        let mut motion = Motion::default();
+        motion.set_recording(true);
+        motion.advance(0.0);
+        motion.record(10.0, Dw6Param::Cutoff, 20);
+        motion.record(10.0, Dw6Param::Resonance, 5);
+        motion.set_recording(false);
+        motion.set_muted(Dw6Param::Cutoff, true);
+        motion.advance(0.0);
+        assert_eq!(motion.advance(20.0), [(Dw6Param::Resonance, 5)]);
+    }
+
+    #[test]
+    fn shorter_loop_drops_late_moves() {
+        let mut motion = Motion::default();
+        motion.set_recording(true);
+        motion.advance(0.0);
+        motion.record(10.0, Dw6Param::Cutoff, 20);
+        motion.record(150.0, Dw6Param::Cutoff, 40);
+        motion.set_recording(false);
+        motion.set_length(Division::Bar);
+        motion.advance(0.0);
+        assert_eq!(motion.advance(95.0), [(Dw6Param::Cutoff, 20)]);
+        motion.clear();
+        assert!(motion.is_empty());
+    }
+}
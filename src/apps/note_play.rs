@@ -0,0 +1,174 @@
+//! Plays the DW-6000 from Beatstep pads and sequencer, with transpose and scale quantize.
+//! Note mapping is a plain computation (no embassy types) so it can be exercised on the host.
+
+use heapless::Vec;
+use midi::MidiChannel;
+
+use crate::devices::arturia::beatstep::SeqScale;
+
+/// Max number of notes held at once
+const MAX_HELD: usize = 16;
+
+const MAX_NOTE: u8 = 127;
+
+/// Furthest transpose, up or down
+pub const MAX_TRANSPOSE: i8 = 24;
+
+/// First play pad plays C3, next pads go up the scale
+const PAD_BASE_NOTE: u8 = 48;
+
+#[derive(Debug)]
+pub struct NotePlay {
+    enabled: bool,
+    transpose: i8,
+    scale: SeqScale,
+    // None follows the DW-6000's channel
+    channel: Option<MidiChannel>,
+    // notes sounding, by key as received and note as sent, so note offs match even if settings changed
+    held: Vec<(u8, u8), MAX_HELD>,
+}
+
+impl Default for NotePlay {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            transpose: 0,
+            scale: SeqScale::Chromatic,
+            channel: None,
+            held: Vec::new(),
+        }
+    }
+}
+
+impl NotePlay {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn get_transpose(&self) -> i8 {
+        self.transpose
+    }
+
+    pub fn set_transpose(&mut self, transpose: i8) {
+        self.transpose = transpose.max(-MAX_TRANSPOSE).min(MAX_TRANSPOSE);
+    }
+
+    pub fn get_scale(&self) -> SeqScale {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: SeqScale) {
+        self.scale = scale;
+    }
+
+    pub fn get_channel(&self) -> Option<MidiChannel> {
+        self.channel
+    }
+
+    pub fn set_channel(&mut self, channel: Option<MidiChannel>) {
+        self.channel = channel;
+    }
+
+    /// Note played by a pad, counting scale degrees up from C3
+    pub fn pad_note(&self, pad: u8) -> u8 {
+        let intervals = self.scale.intervals();
+        let octave = pad as usize / intervals.len();
+        let degree = intervals[pad as usize % intervals.len()];
+        (PAD_BASE_NOTE as usize + octave * 12 + degree as usize).min(MAX_NOTE as usize) as u8
+    }
+
+    /// Nearest scale note at or below note, scales are rooted on C
+    pub fn quantize(&self, note: u8) -> u8 {
+        let intervals = self.scale.intervals();
+        let mut note = note.min(MAX_NOTE);
+        // every scale has its root, C0 is always in scale
+        while !intervals.contains(&(note % 12)) {
+            note -= 1;
+        }
+        note
+    }
+
+    /// Transposed and quantized note to send for key
+    pub fn note_on(&mut self, key: u8, note: u8) -> u8 {
+        let transposed = (note as i16 + self.transpose as i16).max(0).min(MAX_NOTE as i16) as u8;
+        let out = self.quantize(transposed);
+        self.held.retain(|(k, _)| *k != key);
+        let _ = self.held.push((key, out));
+        out
+    }
+
+    /// Note sent when key was pressed, if it was
+    pub fn note_off(&mut self, key: u8) -> Option<u8> {
+        let idx = self.held.iter().position(|(k, _)| *k == key)?;
+        Some(self.held.swap_remove(idx).1)
+    }
+
+    /// Forget all held keys, returning notes to release
+    pub fn release_all(&mut self) -> Vec<u8, MAX_HELD> {
+        let notes = self.held.iter().map(|(_, note)| *note).collect();
+        self.held.clear();
+        notes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantize_down_to_scale() {
+        let mut play = NotePlay::default();
+        assert_eq!(play.quantize(61), 61);
+        play.set_scale(SeqScale::Major);
+        assert_eq!(play.quantize(61), 60);
+        assert_eq!(play.quantize(66), 65);
+        assert_eq!(play.quantize(0), 0);
+        play.set_scale(SeqScale::Blues);
+        assert_eq!(play.quantize(64), 63);
+    }
+
+    #[test]
+    fn pads_go_up_the_scale() {
+        let mut play = NotePlay::default();
+        assert_eq!(play.pad_note(0), 48);
+        assert_eq!(play.pad_note(7), 55);
+        play.set_scale(SeqScale::Major);
+        assert_eq!(play.pad_note(2), 52);
+        assert_eq!(play.pad_note(7), 60);
+    }
+
+    #[test]
+    fn transposed_in_scale() {
+        let mut play = NotePlay::default();
+        play.set_scale(SeqScale::Minor);
+        play.set_transpose(2);
+        assert_eq!(play.note_on(60, 60), 62);
+        assert_eq!(play.note_on(62, 62), 63);
+        play.set_transpose(-100);
+        assert_eq!(play.get_transpose(), -MAX_TRANSPOSE);
+        assert_eq!(play.note_on(10, 10), 0);
+    }
+
+    #[test]
+    fn note_off_matches_note_on() {
+        let mut play = NotePlay::default();
+        play.set_transpose(12);
+        assert_eq!(play.note_on(8, 48), 60);
+        play.set_transpose(0);
+        assert_eq!(play.note_off(8), Some(60));
+        assert_eq!(play.note_off(8), None);
+    }
+
+    #[test]
+    fn release_all_clears() {
+        let mut play = NotePlay::default();
+        play.note_on(60, 60);
+        play.note_on(64, 64);
+        assert_eq!(play.release_all(), [60, 64]);
+        assert_eq!(play.note_off(60), None);
+    }
+}
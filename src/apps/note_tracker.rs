@@ -0,0 +1,354 @@
+//! Follows notes sent to the synth, per channel.
+//! Sustain and sostenuto pedals defer note offs, mono modes sound a single note per channel picked by priority.
+//! Note events are plain values (no embassy types) so the tracker can be exercised on the host.
+
+use heapless::Vec;
+use midi::MidiChannel;
+use num_enum::FromPrimitive;
+
+/// Max number of keys down or held by pedals at once
+const MAX_KEYS: usize = 32;
+
+/// Switching from mono to poly can turn every key on after turning the mono note off
+pub const MAX_EVENTS: usize = MAX_KEYS * 2;
+
+pub const SUSTAIN_CC: u8 = 64;
+pub const SOSTENUTO_CC: u8 = 66;
+
+/// Pedal is down from this CC value
+const PEDAL_DOWN: u8 = 64;
+
+#[derive(Debug, FromPrimitive, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+#[derive(defmt::Format)]
+pub enum Priority {
+    #[num_enum(default)]
+    Poly,
+    Last,
+    Low,
+    High,
+}
+
+impl Priority {
+    pub const COUNT: u8 = 4;
+}
+
+/// Note to turn on or off at the synth
+#[derive(Debug, Copy, Clone)]
+#[derive(defmt::Format)]
+pub struct NoteEvent {
+    pub channel: MidiChannel,
+    pub note: u8,
+    pub velocity: u8,
+    pub on: bool,
+}
+
+pub type NoteEvents = Vec<NoteEvent, MAX_EVENTS>;
+
+#[derive(Debug, Copy, Clone)]
+struct Key {
+    channel: MidiChannel,
+    note: u8,
+    velocity: u8,
+    // physically held down, else held by a pedal
+    down: bool,
+    // was down when sostenuto pedal was pressed
+    sostenuto: bool,
+}
+
+fn same_channel(a: MidiChannel, b: MidiChannel) -> bool {
+    a as u8 == b as u8
+}
+
+fn channel_bit(channel: MidiChannel) -> u16 {
+    1 << channel as u16
+}
+
+#[derive(Debug)]
+pub struct NoteTracker {
+    priority: Priority,
+    // mono note changes turn the new note on before the old one off, so envelopes are not retriggered
+    legato: bool,
+    // in the order they were pressed
+    keys: Vec<Key, MAX_KEYS>,
+    // one bit per channel
+    sustain: u16,
+    sostenuto: u16,
+    // notes on at the synth, with their channel
+    sounding: Vec<(MidiChannel, u8), MAX_KEYS>,
+}
+
+impl Default for NoteTracker {
+    fn default() -> Self {
+        Self {
+            priority: Priority::Poly,
+            legato: false,
+            keys: Vec::new(),
+            sustain: 0,
+            sostenuto: 0,
+            sounding: Vec::new(),
+        }
+    }
+}
+
+impl NoteTracker {
+    pub fn get_priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Notes sounding are switched over to the new priority
+    pub fn set_priority(&mut self, priority: Priority) -> NoteEvents {
+        self.priority = priority;
+        let mut events = NoteEvents::new();
+        let channels: Vec<MidiChannel, MAX_EVENTS> = self.keys.iter().map(|k| k.channel)
+            .chain(self.sounding.iter().map(|(ch, _)| *ch))
+            .collect();
+        for (idx, channel) in channels.iter().enumerate() {
+            if !channels[..idx].iter().any(|ch| same_channel(*ch, *channel)) {
+                self.sync(*channel, &mut events);
+            }
+        }
+        events
+    }
+
+    pub fn is_legato(&self) -> bool {
+        self.legato
+    }
+
+    pub fn set_legato(&mut self, legato: bool) {
+        self.legato = legato;
+    }
+
+    pub fn note_on(&mut self, channel: MidiChannel, note: u8, velocity: u8) -> NoteEvents {
+        let mut events = NoteEvents::new();
+        self.keys.retain(|k| !(same_channel(k.channel, channel) && k.note == note));
+        if self.keys.push(Key { channel, note, velocity, down: true, sostenuto: false }).is_err() {
+            return events;
+        }
+        // key pressed again while held by a pedal, or still sounding in mono
+        if let Some(idx) = self.sounding_idx(channel, note) {
+            self.sounding.swap_remove(idx);
+            let _ = events.push(NoteEvent { channel, note, velocity: 0, on: false });
+        }
+        self.sync(channel, &mut events);
+        events
+    }
+
+    pub fn note_off(&mut self, channel: MidiChannel, note: u8) -> NoteEvents {
+        let mut events = NoteEvents::new();
+        let sustain = self.sustain & channel_bit(channel) != 0;
+        if let Some(idx) = self.keys.iter().position(|k| same_channel(k.channel, channel) && k.note == note) {
+            let key = &mut self.keys[idx];
+            key.down = false;
+            if !sustain && !key.sostenuto {
+                self.keys.remove(idx);
+            }
+        }
+        self.sync(channel, &mut events);
+        events
+    }
+
+    /// Follow sustain and sostenuto pedals, other CCs are ignored
+    pub fn pedal(&mut self, channel: MidiChannel, cc: u8, value: u8) -> NoteEvents {
+        let mut events = NoteEvents::new();
+        let bit = channel_bit(channel);
+        let down = value >= PEDAL_DOWN;
+        match cc {
+            SUSTAIN_CC if down => self.sustain |= bit,
+            SUSTAIN_CC => self.sustain &= !bit,
+            SOSTENUTO_CC if down => {
+                // only keys down when the pedal is pressed are held
+                if self.sostenuto & bit == 0 {
+                    for key in self.keys.iter_mut().filter(|k| same_channel(k.channel, channel) && k.down) {
+                        key.sostenuto = true;
+                    }
+                }
+                self.sostenuto |= bit
+            }
+            SOSTENUTO_CC => {
+                self.sostenuto &= !bit;
+                for key in self.keys.iter_mut().filter(|k| same_channel(k.channel, channel)) {
+                    key.sostenuto = false;
+                }
+            }
+            _ => return events,
+        }
+        let sustain = self.sustain & bit != 0;
+        self.keys.retain(|k| !same_channel(k.channel, channel) || k.down || k.sostenuto || sustain);
+        self.sync(channel, &mut events);
+        events
+    }
+
+    /// Turn off every sounding note and forget all keys, pedals included
+    pub fn release_all(&mut self) -> NoteEvents {
+        let events = self.sounding.iter()
+            .map(|(channel, note)| NoteEvent { channel: *channel, note: *note, velocity: 0, on: false })
+            .collect();
+        self.sounding.clear();
+        self.keys.clear();
+        self.sustain = 0;
+        self.sostenuto = 0;
+        events
+    }
+
+    fn sounding_idx(&self, channel: MidiChannel, note: u8) -> Option<usize> {
+        self.sounding.iter().position(|(ch, n)| same_channel(*ch, channel) && *n == note)
+    }
+
+    /// Keys that should be sounding on channel
+    fn wanted(&self, channel: MidiChannel) -> Vec<Key, MAX_KEYS> {
+        let keys = self.keys.iter().filter(|k| same_channel(k.channel, channel)).copied();
+        let mono = match self.priority {
+            Priority::Poly => return keys.collect(),
+            Priority::Last => keys.last(),
+            Priority::Low => keys.min_by_key(|k| k.note),
+            Priority::High => keys.max_by_key(|k| k.note),
+        };
+        mono.into_iter().collect()
+    }
+
+    /// Turn notes on and off so that channel sounds the wanted keys
+    fn sync(&mut self, channel: MidiChannel, events: &mut NoteEvents) {
+        let wanted = self.wanted(channel);
+        let offs: Vec<u8, MAX_KEYS> = self.sounding.iter()
+            .filter(|(ch, n)| same_channel(*ch, channel) && !wanted.iter().any(|k| k.note == *n))
+            .map(|(_, n)| *n)
+            .collect();
+        let ons: Vec<Key, MAX_KEYS> = wanted.into_iter()
+            .filter(|k| self.sounding_idx(channel, k.note).is_none())
+            .collect();
+
+        let legato = self.legato && self.priority != Priority::Poly;
+        if !legato {
+            self.turn_off(channel, &offs, events);
+        }
+        for key in ons {
+            if self.sounding.push((channel, key.note)).is_ok() {
+                let _ = events.push(NoteEvent { channel, note: key.note, velocity: key.velocity, on: true });
+            }
+        }
+        if legato {
+            self.turn_off(channel, &offs, events);
+        }
+    }
+
+    fn turn_off(&mut self, channel: MidiChannel, notes: &[u8], events: &mut NoteEvents) {
+        for note in notes {
+            if let Some(idx) = self.sounding_idx(channel, *note) {
+                self.sounding.swap_remove(idx);
+                let _ = events.push(NoteEvent { channel, note: *note, velocity: 0, on: false });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CH1: MidiChannel = MidiChannel::CH1;
+    const CH2: MidiChannel = MidiChannel::CH2;
+
+    /// Notes turned on (positive) or off (negative)
+    fn notes(events: NoteEvents) -> Vec<i16, MAX_EVENTS> {
+        events.iter().map(|e| if e.on { e.note as i16 } else { -(e.note as i16) }).collect()
+    }
+
+    #[test]
+    fn poly_on_off() {
+        let mut nt = NoteTracker::default();
+        assert_eq!(notes(nt.note_on(CH1, 60, 100)), [60]);
+        assert_eq!(notes(nt.note_on(CH1, 64, 100)), [64]);
+        assert_eq!(notes(nt.note_off(CH1, 60)), [-60]);
+        assert_eq!(notes(nt.note_off(CH1, 64)), [-64]);
+        assert!(nt.note_off(CH1, 64).is_empty());
+    }
+
+    #[test]
+    fn sustain_defers_note_offs() {
+        let mut nt = NoteTracker::default();
+        nt.note_on(CH1, 60, 100);
+        nt.pedal(CH1, SUSTAIN_CC, 127);
+        assert!(nt.note_off(CH1, 60).is_empty());
+        nt.note_on(CH1, 64, 100);
+        // pressed again while sustained
+        assert_eq!(notes(nt.note_on(CH1, 60, 100)), [-60, 60]);
+        nt.note_off(CH1, 60);
+        assert_eq!(notes(nt.pedal(CH1, SUSTAIN_CC, 0)), [-60]);
+        assert_eq!(notes(nt.note_off(CH1, 64)), [-64]);
+    }
+
+    #[test]
+    fn sostenuto_holds_keys_down_when_pressed() {
+        let mut nt = NoteTracker::default();
+        nt.note_on(CH1, 60, 100);
+        nt.pedal(CH1, SOSTENUTO_CC, 127);
+        nt.note_on(CH1, 64, 100);
+        assert!(nt.note_off(CH1, 60).is_empty());
+        assert_eq!(notes(nt.note_off(CH1, 64)), [-64]);
+        assert_eq!(notes(nt.pedal(CH1, SOSTENUTO_CC, 0)), [-60]);
+    }
+
+    #[test]
+    fn pedals_per_channel() {
+        let mut nt = NoteTracker::default();
+        nt.note_on(CH1, 60, 100);
+        nt.note_on(CH2, 60, 100);
+        nt.pedal(CH1, SUSTAIN_CC, 127);
+        assert!(nt.note_off(CH1, 60).is_empty());
+        assert_eq!(notes(nt.note_off(CH2, 60)), [-60]);
+    }
+
+    #[test]
+    fn mono_last_returns_to_held_note() {
+        let mut nt = NoteTracker::default();
+        nt.set_priority(Priority::Last);
+        nt.note_on(CH1, 60, 100);
+        assert_eq!(notes(nt.note_on(CH1, 64, 100)), [-60, 64]);
+        assert_eq!(notes(nt.note_off(CH1, 64)), [-64, 60]);
+        assert_eq!(notes(nt.note_off(CH1, 60)), [-60]);
+    }
+
+    #[test]
+    fn legato_overlaps_notes() {
+        let mut nt = NoteTracker::default();
+        nt.set_priority(Priority::Last);
+        nt.set_legato(true);
+        nt.note_on(CH1, 60, 100);
+        assert_eq!(notes(nt.note_on(CH1, 64, 100)), [64, -60]);
+        assert_eq!(notes(nt.note_off(CH1, 64)), [60, -64]);
+    }
+
+    #[test]
+    fn low_and_high_priority() {
+        let mut nt = NoteTracker::default();
+        nt.set_priority(Priority::Low);
+        nt.note_on(CH1, 60, 100);
+        assert!(nt.note_on(CH1, 64, 100).is_empty());
+        assert_eq!(notes(nt.note_on(CH1, 55, 100)), [-60, 55]);
+        assert_eq!(notes(nt.set_priority(Priority::High)), [-55, 64]);
+        assert_eq!(notes(nt.set_priority(Priority::Poly)), [60, 55]);
+    }
+
+    #[test]
+    fn release_all_forgets_everything() {
+        let mut nt = NoteTracker::default();
+        nt.pedal(CH1, SUSTAIN_CC, 127);
+        nt.note_on(CH1, 60, 100);
+        nt.note_on(CH2, 62, 100);
+        nt.note_off(CH1, 60);
+        assert_eq!(notes(nt.release_all()), [-60, -62]);
+        assert!(nt.pedal(CH1, SUSTAIN_CC, 0).is_empty());
+        assert!(nt.note_off(CH2, 62).is_empty());
+    }
+
+    #[test]
+    fn release_all_forgets_pedals() {
+        let mut nt = NoteTracker::default();
+        nt.pedal(CH1, SUSTAIN_CC, 127);
+        nt.pedal(CH1, SOSTENUTO_CC, 127);
+        nt.release_all();
+        nt.note_on(CH1, 60, 100);
+        assert_eq!(notes(nt.note_off(CH1, 60)), [-60]);
+    }
+}
@@ -0,0 +1,286 @@
+//! Outbound parameter changes for a slow synth link
+//! Only the latest value of each parameter is kept, values the synth already has are skipped
+//! and messages are spaced by a minimum gap so the receiving end can keep up
+
+use embassy_time::Duration;
+
+/// Param stored in some bits of a dump byte, other params may share the byte
+pub trait PackedParam: Copy {
+    /// Dump byte holding the param, as indexed by the queue
+    fn index(&self) -> u8;
+
+    fn get(&self, dump: &[u8]) -> u8;
+
+    fn set(&self, value: u8, dump: &mut [u8]);
+}
+
+#[derive(Debug)]
+pub struct ParamQueue<const N: usize> {
+    // latest value waiting to be sent, per param index
+    pending: [Option<u8>; N],
+    // last value sent to (or received from) the synth
+    sent: [Option<u8>; N],
+    // pushed since the synth was last asked for a dump, its reply may not have these values yet
+    edited: [bool; N],
+    // synth's value of edited bytes before the edits, tells which params of the byte were edited
+    base: [Option<u8>; N],
+    // round robin, so one busy param doesn't starve the others
+    cursor: usize,
+    min_gap: Duration,
+}
+
+impl<const N: usize> ParamQueue<N> {
+    pub const fn new(min_gap: Duration) -> Self {
+        Self {
+            pending: [None; N],
+            sent: [None; N],
+            edited: [false; N],
+            base: [None; N],
+            cursor: 0,
+            min_gap,
+        }
+    }
+
+    /// Queue value for param, replacing any value not sent yet
+    pub fn push(&mut self, index: u8, value: u8) {
+        if let Some(pending) = self.pending.get_mut(index as usize) {
+            *pending = Some(value);
+            if !self.edited[index as usize] {
+                self.edited[index as usize] = true;
+                self.base[index as usize] = self.sent[index as usize];
+            }
+        }
+    }
+
+    /// Next param value to send, if any differs from what the synth has
+    pub fn pop(&mut self) -> Option<(u8, u8)> {
+        for _ in 0..N {
+            let index = self.cursor;
+            self.cursor = (self.cursor + 1) % N;
+            if let Some(value) = self.pending[index].take() {
+                if self.sent[index] != Some(value) {
+                    self.sent[index] = Some(value);
+                    return Some((index as u8, value));
+                }
+            }
+        }
+        None
+    }
+
+    /// Value was sent to the synth without going through the queue, e.g. right before a note
+    pub fn set_sent(&mut self, index: u8, value: u8) {
+        if let Some(sent) = self.sent.get_mut(index as usize) {
+            *sent = Some(value);
+        }
+    }
+
+    /// Synth values are known from a dump, values not sent yet will still be
+    pub fn sync(&mut self, values: &[u8]) {
+        for ((sent, pending), value) in self.sent.iter_mut().zip(&self.pending).zip(values) {
+            if pending.is_none() {
+                *sent = Some(*value)
+            }
+        }
+    }
+
+    /// Synth was asked for a dump, values sent from now on may be missing from its reply
+    pub fn mark(&mut self) {
+        for (edited, pending) in self.edited.iter_mut().zip(&self.pending) {
+            *edited = pending.is_some();
+        }
+    }
+
+    /// Put back values from edits the synth's dump may predate
+    /// Params sharing a byte with an edited param keep the dump's value, they may have been changed on the synth
+    pub fn merge<P: PackedParam>(&self, dump: &mut [u8], params: &[P]) {
+        let len = dump.len().min(N);
+        let mut latest = [0; N];
+        let mut base = [0; N];
+        latest[..len].copy_from_slice(&dump[..len]);
+        base[..len].copy_from_slice(&dump[..len]);
+        for index in 0..len {
+            if self.edited[index] {
+                latest[index] = self.pending[index].or(self.sent[index]).unwrap_or(dump[index]);
+                base[index] = self.base[index].unwrap_or(dump[index]);
+            }
+        }
+        for param in params {
+            let index = param.index() as usize;
+            if index >= len || !self.edited[index] {
+                continue;
+            }
+            let value = param.get(&latest);
+            // whole byte is taken if its value before the edit is unknown
+            if self.base[index].is_none() || param.get(&base) != value {
+                param.set(value, dump);
+            }
+        }
+    }
+
+    /// Drop values not sent yet, e.g. when a whole patch is loaded
+    pub fn cancel(&mut self) {
+        self.pending = [None; N];
+        self.edited = [false; N];
+    }
+
+    /// Synth values changed behind our back (e.g. program change), resend everything
+    pub fn forget(&mut self) {
+        self.sent = [None; N];
+    }
+
+    pub fn get_min_gap(&self) -> Duration {
+        self.min_gap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue() -> ParamQueue<4> {
+        ParamQueue::new(Duration::from_millis(15))
+    }
+
+    /// Params 0 to 2 take a whole byte, byte 3 holds two nibbles
+    #[derive(Copy, Clone)]
+    enum Param {
+        Byte(u8),
+        Low,
+        High,
+    }
+
+    const PARAMS: [Param; 5] = [Param::Byte(0), Param::Byte(1), Param::Byte(2), Param::Low, Param::High];
+
+    impl PackedParam for Param {
+        fn index(&self) -> u8 {
+            match self {
+                Param::Byte(index) => *index,
+                Param::Low | Param::High => 3,
+            }
+        }
+
+        fn get(&self, dump: &[u8]) -> u8 {
+            match self {
+                Param::Byte(index) => dump[*index as usize],
+                Param::Low => dump[3] & 0x0f,
+                Param::High => dump[3] >> 4,
+            }
+        }
+
+        fn set(&self, value: u8, dump: &mut [u8]) {
+            match self {
+                Param::Byte(index) => dump[*index as usize] = value,
+                Param::Low => dump[3] = dump[3] & 0xf0 | value,
+                Param::High => dump[3] = dump[3] & 0x0f | value << 4,
+            }
+        }
+    }
+
+    #[test]
+    fn latest_value_only() {
+        let mut queue = queue();
+        queue.push(1, 10);
+        queue.push(1, 20);
+        assert_eq!(queue.pop(), Some((1, 20)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn values_already_sent_skipped() {
+        let mut queue = queue();
+        queue.push(1, 10);
+        queue.pop();
+        queue.push(1, 10);
+        assert_eq!(queue.pop(), None);
+        queue.sync(&[0, 30, 0, 0]);
+        queue.push(1, 30);
+        assert_eq!(queue.pop(), None);
+        queue.forget();
+        queue.push(1, 30);
+        assert_eq!(queue.pop(), Some((1, 30)));
+    }
+
+    #[test]
+    fn value_sent_outside_queue_resent() {
+        let mut queue = queue();
+        queue.push(1, 10);
+        queue.pop();
+        queue.set_sent(1, 12);
+        queue.push(1, 10);
+        assert_eq!(queue.pop(), Some((1, 10)));
+    }
+
+    #[test]
+    fn round_robin() {
+        let mut queue = queue();
+        queue.push(0, 1);
+        queue.push(2, 1);
+        assert_eq!(queue.pop(), Some((0, 1)));
+        // busy param waits for its turn
+        queue.push(0, 2);
+        assert_eq!(queue.pop(), Some((2, 1)));
+        assert_eq!(queue.pop(), Some((0, 2)));
+    }
+
+    #[test]
+    fn out_of_range_ignored() {
+        let mut queue = queue();
+        queue.push(4, 1);
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn merge_restores_edits_missing_from_dump() {
+        let mut queue = queue();
+        queue.push(0, 5);
+        queue.pop();
+        queue.push(1, 6);
+        queue.mark();
+        // param 0 was sent before the dump request, param 1 is still pending
+        let mut dump = [5, 0, 0, 0];
+        queue.merge(&mut dump, &PARAMS);
+        assert_eq!(dump, [5, 6, 0, 0]);
+        // sent after the dump request, dump may predate it
+        queue.push(2, 7);
+        queue.pop();
+        let mut dump = [5, 0, 0, 0];
+        queue.merge(&mut dump, &PARAMS);
+        assert_eq!(dump, [5, 6, 7, 0]);
+    }
+
+    #[test]
+    fn cancel_drops_pending() {
+        let mut queue = queue();
+        queue.push(0, 5);
+        queue.cancel();
+        assert_eq!(queue.pop(), None);
+        let mut dump = [1, 2, 3, 4];
+        queue.merge(&mut dump, &PARAMS);
+        assert_eq!(dump, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn merge_keeps_synth_value_of_param_sharing_byte() {
+        let mut queue = queue();
+        queue.sync(&[0, 0, 0, 0x21]);
+        // low nibble edited here
+        queue.push(3, 0x25);
+        queue.pop();
+        queue.mark();
+        queue.push(3, 0x26);
+        // dump predates the edit, high nibble was changed on the synth
+        let mut dump = [0, 0, 0, 0x31];
+        queue.merge(&mut dump, &PARAMS);
+        assert_eq!(dump, [0, 0, 0, 0x36]);
+    }
+
+    #[test]
+    fn merge_takes_whole_byte_if_synth_value_unknown() {
+        let mut queue = queue();
+        queue.push(3, 0x25);
+        queue.mark();
+        let mut dump = [0, 0, 0, 0x31];
+        queue.merge(&mut dump, &PARAMS);
+        assert_eq!(dump, [0, 0, 0, 0x25]);
+    }
+}
@@ -0,0 +1,197 @@
+//! Generates new DW-6000 patches, from scratch or by mutating the current one
+//! Locked params are never changed, values always stay within each param's range
+
+use num_enum::FromPrimitive;
+
+use crate::chaos::WyRand;
+use crate::devices::korg::dw6000::{self, Dw6Param};
+use crate::scale::Curve;
+
+/// Params randomized together
+#[derive(Debug, FromPrimitive, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+#[derive(defmt::Format)]
+pub enum ParamGroup {
+    #[num_enum(default)]
+    All,
+    Osc,
+    Filter,
+    Env,
+    Mod,
+}
+
+impl ParamGroup {
+    pub fn contains(&self, param: Dw6Param) -> bool {
+        use Dw6Param::*;
+        match self {
+            ParamGroup::All => true,
+            ParamGroup::Osc => matches!(param,
+                Osc1Wave | Osc1Level | Osc1Octave | Osc2Wave | Osc2Level | Osc2Octave | Osc2Detune | Interval | Noise),
+            ParamGroup::Filter => matches!(param,
+                Cutoff | Resonance | VcfInt | KbdTrack),
+            ParamGroup::Env => matches!(param,
+                VcfAttack | VcfDecay | VcfBreak | VcfSlope | VcfSustain | VcfRelease |
+                VcaAttack | VcaDecay | VcaBreak | VcaSlope | VcaSustain | VcaRelease),
+            ParamGroup::Mod => matches!(param,
+                MgFreq | MgDelay | MgOsc | MgVcf | BendVcf | BendOsc | Portamento),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Randomizer {
+    chaos: WyRand,
+    // one bit per param, in Dw6Param order
+    locks: u64,
+    // how far a mutation can move each param, between 0 and 1
+    amount: f32,
+}
+
+impl Default for Randomizer {
+    fn default() -> Self {
+        Self {
+            chaos: WyRand::default(),
+            locks: 0,
+            amount: 0.15,
+        }
+    }
+}
+
+impl Randomizer {
+    pub fn seed(&mut self, seed: u64) {
+        self.chaos = WyRand::new(seed);
+    }
+
+    pub fn is_locked(&self, param: Dw6Param) -> bool {
+        self.locks & (1 << param as u64) != 0
+    }
+
+    pub fn set_locked(&mut self, param: Dw6Param, locked: bool) {
+        if locked {
+            self.locks |= 1 << param as u64;
+        } else {
+            self.locks &= !(1 << param as u64);
+        }
+    }
+
+    pub fn get_amount(&self) -> f32 {
+        self.amount
+    }
+
+    pub fn set_amount(&mut self, amount: f32) {
+        self.amount = amount.max(0.0).min(1.0);
+    }
+
+    fn unlocked(&self, group: ParamGroup) -> impl Iterator<Item=Dw6Param> + '_ {
+        Dw6Param::ALL.into_iter().filter(move |p| group.contains(*p) && !self.is_locked(*p))
+    }
+
+    /// Replace group's params with random values
+    pub fn randomize(&mut self, patch: &mut [u8], group: ParamGroup) {
+        for param in self.unlocked(group).collect::<heapless::Vec<_, 35>>() {
+            let value = self.chaos.range(param.max_value() as u32) as u8;
+            dw6000::set_param_value(param, value, patch);
+        }
+        self.audible(patch);
+    }
+
+    /// Move every unlocked param by a random amount
+    /// Stepped params (waveforms, octaves, switches) change value with a probability of `amount`
+    pub fn mutate(&mut self, patch: &mut [u8]) {
+        for param in self.unlocked(ParamGroup::All).collect::<heapless::Vec<_, 35>>() {
+            let max = param.max_value();
+            let value = dw6000::get_param_value(param, patch);
+            let value = match param.scale().curve {
+                Curve::Stepped if self.chaos.unipolar() < self.amount => self.chaos.range(max as u32) as u8,
+                Curve::Stepped => value,
+                _ => {
+                    let delta = self.chaos.bipolar() * self.amount * max as f32;
+                    (value as f32 + delta + 0.5).max(0.0).min(max as f32) as u8
+                }
+            };
+            dw6000::set_param_value(param, value, patch);
+        }
+        self.audible(patch);
+    }
+
+    /// Avoid silent patches, unless osc levels were locked that way
+    fn audible(&self, patch: &mut [u8]) {
+        use Dw6Param::*;
+        let quiet = Osc1Level.max_value() / 4;
+        if dw6000::get_param_value(Osc1Level, patch) < quiet
+            && dw6000::get_param_value(Osc2Level, patch) < quiet
+            && dw6000::get_param_value(Noise, patch) < quiet
+            && !self.is_locked(Osc1Level) {
+            dw6000::set_param_value(Osc1Level, Osc1Level.max_value(), patch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP_LENGTH: usize = 26;
+
+    fn random_patch(seed: u64) -> [u8; DUMP_LENGTH] {
+        let mut patch = [0; DUMP_LENGTH];
+        let mut rnd = Randomizer::default();
+        rnd.seed(seed);
+        rnd.randomize(&mut patch, ParamGroup::All);
+        patch
+    }
+
+    #[test]
+    fn same_seed_same_patch() {
+        assert_eq!(random_patch(42), random_patch(42));
+        assert_ne!(random_patch(42), random_patch(43));
+    }
+
+    #[test]
+    fn values_in_range() {
+        for seed in 0..50 {
+            let patch = random_patch(seed);
+            for param in Dw6Param::ALL {
+                assert!(dw6000::get_param_value(param, &patch) <= param.max_value());
+            }
+        }
+    }
+
+    #[test]
+    fn locked_params_unchanged() {
+        let mut patch = [0; DUMP_LENGTH];
+        let mut rnd = Randomizer::default();
+        rnd.set_locked(Dw6Param::Cutoff, true);
+        rnd.set_locked(Dw6Param::Osc1Wave, true);
+        for _ in 0..20 {
+            rnd.randomize(&mut patch, ParamGroup::All);
+            rnd.mutate(&mut patch);
+            assert_eq!(dw6000::get_param_value(Dw6Param::Cutoff, &patch), 0);
+            assert_eq!(dw6000::get_param_value(Dw6Param::Osc1Wave, &patch), 0);
+        }
+    }
+
+    #[test]
+    fn group_only() {
+        let mut patch = random_patch(7);
+        let before = patch;
+        let mut rnd = Randomizer::default();
+        rnd.randomize(&mut patch, ParamGroup::Env);
+        for param in Dw6Param::ALL {
+            if !ParamGroup::Env.contains(param) {
+                assert_eq!(dw6000::get_param_value(param, &patch), dw6000::get_param_value(param, &before));
+            }
+        }
+        assert_ne!(patch, before);
+    }
+
+    #[test]
+    fn zero_mutation_keeps_patch() {
+        let mut patch = random_patch(3);
+        let before = patch;
+        let mut rnd = Randomizer::default();
+        rnd.set_amount(0.0);
+        rnd.mutate(&mut patch);
+        assert_eq!(patch, before);
+    }
+}
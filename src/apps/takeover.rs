@@ -0,0 +1,224 @@
+//! Soft takeover, keeps Beatstep knobs from overwriting patch values
+//! when their position doesn't match the value currently loaded in the DW-6000
+//! Relative encoders have no such problem, they always move from the current value
+
+use num_enum::FromPrimitive;
+
+use crate::scale::Scale;
+
+/// Beatstep knobs 1 to 16 and jog wheel (17)
+pub const KNOB_COUNT: usize = 17;
+
+const KNOB_MAX: u8 = 127;
+
+#[derive(Debug, FromPrimitive, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+#[derive(defmt::Format)]
+pub enum TakeoverMode {
+    /// Value follows knob immediately
+    Jump,
+    /// Knob is ignored until it reaches the patch value
+    #[num_enum(default)]
+    Pickup,
+    /// Value moves in knob direction, proportionally converging with knob position
+    Scale,
+}
+
+impl TakeoverMode {
+    pub const COUNT: u8 = 3;
+}
+
+#[derive(Debug)]
+pub struct Takeover {
+    modes: [TakeoverMode; KNOB_COUNT],
+    // last position received from each knob
+    last: [Option<u8>; KNOB_COUNT],
+    // knob reached the patch value and now drives it
+    picked_up: [bool; KNOB_COUNT],
+}
+
+impl Default for Takeover {
+    fn default() -> Self {
+        let mut modes = [TakeoverMode::Pickup; KNOB_COUNT];
+        // jog wheel is endless, it has no position to pick up
+        modes[KNOB_COUNT - 1] = TakeoverMode::Jump;
+        Self {
+            modes,
+            last: [None; KNOB_COUNT],
+            picked_up: [false; KNOB_COUNT],
+        }
+    }
+}
+
+impl Takeover {
+    pub fn get_mode(&self, knob: usize) -> TakeoverMode {
+        self.modes.get(knob).copied().unwrap_or(TakeoverMode::Jump)
+    }
+
+    pub fn set_mode(&mut self, knob: usize, mode: TakeoverMode) {
+        if let Some(m) = self.modes.get_mut(knob) {
+            *m = mode
+        }
+    }
+
+    /// Knob has to take over its value again, e.g. after it was changed from the synth's panel
+    pub fn release(&mut self, knob: usize) {
+        if let Some(last) = self.last.get_mut(knob) {
+            *last = None;
+            self.picked_up[knob] = false;
+        }
+    }
+
+    /// All knobs have to take over again, e.g. after a program change
+    pub fn release_all(&mut self) {
+        self.last = [None; KNOB_COUNT];
+        self.picked_up = [false; KNOB_COUNT];
+    }
+
+    /// Param value to apply for a new knob position, given the patch's current value
+    /// Returns None if the knob has not taken over the value yet
+    pub fn update(&mut self, knob: usize, position: u8, value: u8, scale: &Scale) -> Option<u8> {
+        let last = match self.last.get_mut(knob) {
+            Some(last) => last.replace(position),
+            None => return Some(scale.from_cc(position)),
+        };
+        if scale.from_cc(position) == value {
+            self.picked_up[knob] = true;
+            return Some(value);
+        }
+        let reference = scale.to_cc(value);
+        let position = match self.modes[knob] {
+            TakeoverMode::Jump => Some(position),
+            TakeoverMode::Pickup => {
+                let picked_up = match last {
+                    // patch still holds the value this knob last set
+                    Some(last) if self.picked_up[knob] && scale.from_cc(last) == value => true,
+                    // knob went over patch value since last move
+                    Some(last) => (last < reference) != (position < reference),
+                    None => false,
+                };
+                self.picked_up[knob] = picked_up;
+                picked_up.then_some(position)
+            }
+            TakeoverMode::Scale => match last {
+                Some(last) if position > last => {
+                    // cover remaining value travel over remaining knob travel
+                    let span = KNOB_MAX - last;
+                    let remain = KNOB_MAX.saturating_sub(reference);
+                    Some(reference + ((position - last) as u16 * remain as u16 / span as u16) as u8)
+                }
+                Some(last) if position < last => {
+                    Some(reference - ((last - position) as u16 * reference as u16 / last as u16) as u8)
+                }
+                _ => None,
+            }
+        };
+        position.map(|p| scale.from_cc(p))
+    }
+
+    /// Param value after moving a relative encoder by `step`
+    /// Virtual knob position restarts from the patch value whenever they don't match anymore
+    pub fn relative(&mut self, knob: usize, step: i8, value: u8, scale: &Scale) -> u8 {
+        let last = match self.last.get_mut(knob) {
+            Some(last) => last,
+            None => return value,
+        };
+        let start = match *last {
+            Some(position) if scale.from_cc(position) == value => position,
+            _ => scale.to_cc(value),
+        };
+        let position = (start as i16 + step as i16).max(0).min(KNOB_MAX as i16) as u8;
+        *last = Some(position);
+        scale.from_cc(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scale::Curve;
+
+    const SCALE: Scale = Scale::new(127, Curve::Linear);
+
+    fn takeover(mode: TakeoverMode) -> Takeover {
+        let mut takeover = Takeover::default();
+        takeover.set_mode(0, mode);
+        takeover
+    }
+
+    #[test]
+    fn jump_follows_knob() {
+        let mut takeover = takeover(TakeoverMode::Jump);
+        assert_eq!(takeover.update(0, 10, 64, &SCALE), Some(10));
+        assert_eq!(takeover.update(0, 100, 10, &SCALE), Some(100));
+    }
+
+    #[test]
+    fn pickup_waits_for_crossing() {
+        let mut takeover = takeover(TakeoverMode::Pickup);
+        assert_eq!(takeover.update(0, 10, 64, &SCALE), None);
+        assert_eq!(takeover.update(0, 40, 64, &SCALE), None);
+        assert_eq!(takeover.update(0, 70, 64, &SCALE), Some(70));
+    }
+
+    #[test]
+    fn picked_up_keeps_following() {
+        let mut takeover = takeover(TakeoverMode::Pickup);
+        takeover.update(0, 60, 64, &SCALE);
+        assert_eq!(takeover.update(0, 65, 64, &SCALE), Some(65));
+        assert_eq!(takeover.update(0, 66, 65, &SCALE), Some(66));
+        assert_eq!(takeover.update(0, 67, 66, &SCALE), Some(67));
+        assert_eq!(takeover.update(0, 50, 67, &SCALE), Some(50));
+    }
+
+    #[test]
+    fn exact_match_picks_up() {
+        let mut takeover = takeover(TakeoverMode::Pickup);
+        takeover.update(0, 60, 64, &SCALE);
+        assert_eq!(takeover.update(0, 64, 64, &SCALE), Some(64));
+        assert_eq!(takeover.update(0, 65, 64, &SCALE), Some(65));
+    }
+
+    #[test]
+    fn release_drops_pickup() {
+        let mut takeover = takeover(TakeoverMode::Pickup);
+        takeover.update(0, 60, 64, &SCALE);
+        takeover.update(0, 65, 64, &SCALE);
+        takeover.release(0);
+        assert_eq!(takeover.update(0, 66, 65, &SCALE), None);
+        assert_eq!(takeover.update(0, 67, 65, &SCALE), None);
+        takeover.update(0, 66, 65, &SCALE);
+        takeover.release_all();
+        assert_eq!(takeover.update(0, 66, 65, &SCALE), None);
+    }
+
+    #[test]
+    fn value_changed_elsewhere_drops_pickup() {
+        let mut takeover = takeover(TakeoverMode::Pickup);
+        takeover.update(0, 60, 64, &SCALE);
+        takeover.update(0, 65, 64, &SCALE);
+        // patch value moved away from the knob without a release
+        assert_eq!(takeover.update(0, 66, 100, &SCALE), None);
+        assert_eq!(takeover.update(0, 101, 100, &SCALE), Some(101));
+    }
+
+    #[test]
+    fn scale_converges() {
+        let mut takeover = takeover(TakeoverMode::Scale);
+        takeover.update(0, 27, 77, &SCALE);
+        assert_eq!(takeover.update(0, 50, 77, &SCALE), Some(88));
+        assert_eq!(takeover.update(0, 127, 88, &SCALE), Some(127));
+        takeover.update(0, 50, 100, &SCALE);
+        assert_eq!(takeover.update(0, 25, 100, &SCALE), Some(50));
+        assert_eq!(takeover.update(0, 0, 50, &SCALE), Some(0));
+    }
+
+    #[test]
+    fn relative_restarts_from_patch_value() {
+        let mut takeover = Takeover::default();
+        assert_eq!(takeover.relative(0, 2, 64, &SCALE), 66);
+        assert_eq!(takeover.relative(0, -1, 66, &SCALE), 65);
+        assert_eq!(takeover.relative(0, 1, 10, &SCALE), 11);
+        assert_eq!(takeover.relative(0, -20, 11, &SCALE), 0);
+    }
+}
@@ -0,0 +1,217 @@
+//! Fakes velocity sensitivity on the DW-6000, which ignores note velocity.
+//! A param is moved away from its patch value before each note, in proportion to the note's velocity,
+//! and restored once all notes are released.
+
+use heapless::Vec;
+use micromath::F32Ext;
+use num_enum::FromPrimitive;
+
+use crate::devices::korg::dw6000::Dw6Param;
+
+/// Max number of notes held at once
+const MAX_HELD: usize = 16;
+
+const MAX_VELOCITY: u8 = 127;
+
+#[derive(Debug, FromPrimitive, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+#[derive(defmt::Format)]
+pub enum VelocityTarget {
+    #[num_enum(default)]
+    Off,
+    Cutoff,
+    VcfInt,
+    VcaAttack,
+}
+
+impl VelocityTarget {
+    pub const COUNT: u8 = 4;
+
+    pub fn param(&self) -> Option<Dw6Param> {
+        match self {
+            VelocityTarget::Off => None,
+            VelocityTarget::Cutoff => Some(Dw6Param::Cutoff),
+            VelocityTarget::VcfInt => Some(Dw6Param::VcfInt),
+            VelocityTarget::VcaAttack => Some(Dw6Param::VcaAttack),
+        }
+    }
+}
+
+#[derive(Debug, FromPrimitive, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+#[derive(defmt::Format)]
+pub enum VelocityCurve {
+    #[num_enum(default)]
+    Linear,
+    /// Stronger response to soft notes
+    Soft,
+    /// Only hard notes make much of a difference
+    Hard,
+}
+
+impl VelocityCurve {
+    pub const COUNT: u8 = 3;
+
+    fn apply(&self, x: f32) -> f32 {
+        match self {
+            VelocityCurve::Linear => x,
+            VelocityCurve::Soft => x.sqrt(),
+            VelocityCurve::Hard => x * x,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Velocity {
+    target: VelocityTarget,
+    // offset at full velocity, as a fraction of param range
+    depth: f32,
+    curve: VelocityCurve,
+    held: Vec<u8, MAX_HELD>,
+    // param currently away from its patch value
+    active: Option<Dw6Param>,
+}
+
+impl Default for Velocity {
+    fn default() -> Self {
+        Self {
+            target: VelocityTarget::Off,
+            depth: 0.5,
+            curve: VelocityCurve::Linear,
+            held: Vec::new(),
+            active: None,
+        }
+    }
+}
+
+impl Velocity {
+    pub fn get_target(&self) -> VelocityTarget {
+        self.target
+    }
+
+    /// Param already moved stays active until released
+    pub fn set_target(&mut self, target: VelocityTarget) {
+        self.target = target;
+    }
+
+    pub fn get_depth(&self) -> f32 {
+        self.depth
+    }
+
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.max(0.0).min(1.0);
+    }
+
+    pub fn get_curve(&self) -> VelocityCurve {
+        self.curve
+    }
+
+    pub fn set_curve(&mut self, curve: VelocityCurve) {
+        self.curve = curve;
+    }
+
+    /// Param moved for notes currently held, if any
+    pub fn active_param(&self) -> Option<Dw6Param> {
+        self.active
+    }
+
+    /// Param value for a note velocity, harder notes shorten attack instead of raising it
+    pub fn offset(&self, param: Dw6Param, root: u8, velocity: u8) -> u8 {
+        let max = param.max_value() as f32;
+        let x = self.curve.apply(velocity.min(MAX_VELOCITY) as f32 / MAX_VELOCITY as f32);
+        let offset = x * self.depth * max;
+        let value = if param == Dw6Param::VcaAttack { root as f32 - offset } else { root as f32 + offset };
+        value.round().max(0.0).min(max) as u8
+    }
+
+    /// Param value to send before the note, given the target param's patch value
+    /// No root value (no patch yet, or param modulated) plays the note unchanged
+    pub fn note_on(&mut self, note: u8, velocity: u8, root: Option<u8>) -> Option<(Dw6Param, u8)> {
+        if !self.held.contains(&note) {
+            let _ = self.held.push(note);
+        }
+        let param = self.target.param()?;
+        // target changed while notes were held
+        if self.active.is_some_and(|p| p != param) {
+            return None;
+        }
+        let root = root?;
+        self.active = Some(param);
+        Some((param, self.offset(param, root, velocity)))
+    }
+
+    /// Param value to restore after the note, given the active param's patch value
+    pub fn note_off(&mut self, note: u8, root: Option<u8>) -> Option<(Dw6Param, u8)> {
+        self.held.retain(|n| *n != note);
+        if !self.held.is_empty() {
+            return None;
+        }
+        let param = self.active.take()?;
+        Some((param, root?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn velocity(target: VelocityTarget, depth: f32) -> Velocity {
+        let mut vel = Velocity::default();
+        vel.set_target(target);
+        vel.set_depth(depth);
+        vel
+    }
+
+    #[test]
+    fn offset_follows_velocity() {
+        let vel = velocity(VelocityTarget::Cutoff, 0.5);
+        assert_eq!(vel.offset(Dw6Param::Cutoff, 20, 0), 20);
+        assert_eq!(vel.offset(Dw6Param::Cutoff, 20, 127), 52);
+        assert_eq!(vel.offset(Dw6Param::Cutoff, 60, 127), 63);
+    }
+
+    #[test]
+    fn attack_gets_shorter() {
+        let vel = velocity(VelocityTarget::VcaAttack, 1.0);
+        assert_eq!(vel.offset(Dw6Param::VcaAttack, 20, 127), 0);
+        assert!(vel.offset(Dw6Param::VcaAttack, 20, 64) < 20);
+    }
+
+    #[test]
+    fn curves() {
+        let mut vel = velocity(VelocityTarget::Cutoff, 1.0);
+        let linear = vel.offset(Dw6Param::Cutoff, 0, 64);
+        vel.set_curve(VelocityCurve::Soft);
+        assert!(vel.offset(Dw6Param::Cutoff, 0, 64) > linear);
+        vel.set_curve(VelocityCurve::Hard);
+        assert!(vel.offset(Dw6Param::Cutoff, 0, 64) < linear);
+    }
+
+    #[test]
+    fn restored_after_last_note() {
+        let mut vel = velocity(VelocityTarget::Cutoff, 0.5);
+        assert_eq!(vel.note_on(60, 127, Some(20)), Some((Dw6Param::Cutoff, 52)));
+        assert_eq!(vel.note_on(64, 0, Some(20)), Some((Dw6Param::Cutoff, 20)));
+        assert_eq!(vel.note_off(60, Some(20)), None);
+        assert_eq!(vel.note_off(64, Some(20)), Some((Dw6Param::Cutoff, 20)));
+        assert_eq!(vel.active_param(), None);
+    }
+
+    #[test]
+    fn target_change_waits_for_release() {
+        let mut vel = velocity(VelocityTarget::Cutoff, 0.5);
+        vel.note_on(60, 127, Some(20));
+        vel.set_target(VelocityTarget::VcfInt);
+        assert_eq!(vel.note_on(64, 127, Some(10)), None);
+        vel.note_off(64, Some(20));
+        assert_eq!(vel.note_off(60, Some(20)), Some((Dw6Param::Cutoff, 20)));
+        assert_eq!(vel.note_on(60, 127, Some(10)).map(|(p, _)| p), Some(Dw6Param::VcfInt));
+    }
+
+    #[test]
+    fn off_plays_unchanged() {
+        let mut vel = Velocity::default();
+        assert_eq!(vel.note_on(60, 127, Some(20)), None);
+        assert_eq!(vel.note_off(60, Some(20)), None);
+    }
+}
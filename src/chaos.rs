@@ -0,0 +1,51 @@
+//! Pseudo-random numbers for modulation and generative stuff
+//! Seeded from hardware RNG if `rng` feature is enabled (see `entropy::seed`), from a fixed value otherwise
+//! so that sequences are reproducible (e.g. in host tests)
+
+/// Used when there is no hardware RNG
+pub const FIXED_SEED: u64 = 0xD306_0000_5EED_0666;
+
+/// WyRand generator, same as nanorand's
+/// Fast and small, NOT cryptographically secure
+#[derive(Debug, Clone)]
+pub struct WyRand {
+    state: u64,
+}
+
+impl Default for WyRand {
+    fn default() -> Self {
+        Self::new(FIXED_SEED)
+    }
+}
+
+impl WyRand {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0xa076_1d64_78bd_642f);
+        let t = (self.state as u128).wrapping_mul((self.state ^ 0xe703_7ed1_a0b4_28db) as u128);
+        ((t >> 64) ^ t) as u64
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Uniform between 0 and `max` (inclusive)
+    pub fn range(&mut self, max: u32) -> u32 {
+        ((self.next_u32() as u64 * (max as u64 + 1)) >> 32) as u32
+    }
+
+    /// Uniform between 0 and 1
+    pub fn unipolar(&mut self) -> f32 {
+        // 24 bits is all the precision a f32 mantissa can hold
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Uniform between -1 and 1
+    pub fn bipolar(&mut self) -> f32 {
+        self.unipolar() * 2.0 - 1.0
+    }
+}
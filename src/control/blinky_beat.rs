@@ -28,10 +28,10 @@ async fn blinky() -> ! {
     // force set MIDI channel (simulate manual selection with CHAN+PAD)
     // FIXME neither of these work, is setting channel possible?
     //   alt: read current MIDI channel and use it (maybe even better?)
-    midi_send(beatstep::beatstep_set(CVGateChannel(CH1)).into()).await;
-    midi_send(beatstep::beatstep_set(GlobalMidiChannel(CH1)).into()).await;
+    beatstep_send(CVGateChannel(CH1)).await;
+    beatstep_send(GlobalMidiChannel(CH1)).await;
 
-    midi_send(beatstep::beatstep_set(PadNote(Pad(0), z.get().unwrap().channel, Note::C1m, SwitchMode::Gate)).into()).await;
+    beatstep_send(PadNote(Pad(0), z.get().unwrap().channel, Note::C1m, SwitchMode::Gate)).await;
 
     // turn off all LED pads
     for (note, _) in &mut z.get_mut().unwrap().notes {
@@ -48,6 +48,12 @@ async fn blinky() -> ! {
     }
 }
 
+async fn beatstep_send(param: beatstep::Param) {
+    for sysex in beatstep::beatstep_set(param) {
+        midi_send(sysex.into()).await;
+    }
+}
+
 async fn midi_send(packets: PacketList) {
     let mut bs_out = MIDI_DIN_1_OUT.lock().await;
     bs_out.get_mut().unwrap().transmit(packets.into()).await.unwrap();
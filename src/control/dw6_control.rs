@@ -0,0 +1,2302 @@
+//! Sends MIDI to Korg DW-6000 acccording to messages
+//!
+use midi::{MidiMessage, Note, program_change, note_on, note_off, MidiError, U4, U7, PacketList, channel, SysexError, Packet, MidiChannel};
+
+use crate::{AppError, BLINK, midi, MIDI_DIN_1_IN, MIDI_DIN_1_OUT, MIDI_DIN_2_IN, MIDI_DIN_2_OUT, sysex};
+
+use core::convert::TryFrom;
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use embassy_executor::{Spawner, SpawnError};
+use embassy_time::{Instant, Timer, Duration, with_timeout};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+
+use num_enum::TryFromPrimitive;
+use num::{Integer};
+use crate::apps::lfo::Waveform;
+use crate::apps::mod_matrix::{LFO_COUNT, MAX_ROUTES, ModMatrix, ModRoute, ModSource};
+use crate::apps::arp::{Arp, ArpMode};
+use crate::apps::clock::{Division, MidiClock, PPQN};
+use crate::apps::takeover::{KNOB_COUNT, Takeover, TakeoverMode};
+use crate::entropy;
+
+use crate::devices::korg::dw6000;
+use crate::devices::arturia::beatstep::{self, Behavior, Encoder, Pad, SeqScale, SwitchMode};
+
+use hashbrown::HashMap;
+use heapless::Vec;
+
+use midi::{capture_sysex, SysexCapture};
+use crate::devices::korg::dw6000::{Dw6Param, ShortDw6Sysex, PROGRAM_COUNT};
+use crate::apps::param_queue::ParamQueue;
+use crate::apps::history::{Gesture, History, GROUP_LEN};
+use crate::apps::morph::Morph;
+use crate::apps::randomizer::{ParamGroup, Randomizer};
+use crate::apps::mapping::{Assign, Mapping};
+use crate::apps::macro_knob::{Macro, MacroParam, MACRO_MAX};
+use crate::apps::motion::Motion;
+use crate::apps::velocity::{Velocity, VelocityCurve, VelocityTarget};
+use crate::apps::note_play::{NotePlay, MAX_TRANSPOSE};
+use crate::apps::note_tracker::{NoteEvent, NoteTracker, Priority, MAX_EVENTS, SOSTENUTO_CC, SUSTAIN_CC};
+use crate::scale::{Curve, Scale};
+use crate::resource::{Shared};
+use crate::sysex::{SysexSeq, Transaction, TransactionError};
+
+const SHORT_PRESS_MS: Duration = Duration::from_millis(250);
+
+/// Number of patch snapshots kept in RAM
+const SNAPSHOT_SLOTS: usize = 8;
+
+/// How often the arp checks for held notes when it has nothing to play
+const ARP_IDLE_MS: Duration = Duration::from_millis(10);
+
+/// Time between motion loop playback updates
+const MOTION_PERIOD: Duration = Duration::from_millis(10);
+
+/// Motion loop lengths selected from knob
+const MOTION_LENGTHS: [Division; 3] = [Division::Bar, Division::TwoBars, Division::FourBars];
+
+/// Notes below this are Beatstep pads, used for paging and patch selection
+const PAD_NOTES: u8 = 16;
+
+/// Beatstep Recall and Store buttons are set up to send these CCs (general purpose buttons),
+/// notes would clash with the sequencer's
+const UNDO_CC: u8 = 80;
+const REDO_CC: u8 = 81;
+
+/// How Beatstep knobs are configured on startup, and when the Takeover knob selects relative mode
+/// Relative encoders always move from the patch value, absolute knobs go through takeover
+const KNOB_BEHAVIOR: Behavior = Behavior::RelativeCentered64;
+
+/// Knob index of the jog wheel
+const JOG_WHEEL: usize = KNOB_COUNT - 1;
+
+/// Mod wheel is taken from the DW-6000 joystick or USB host, Beatstep uses CC 1 for a knob
+const MOD_WHEEL_CC: u8 = 1;
+
+static DW6_CTRL: Shared<Dw6ControlInner> = Shared::uninit("DW6_CTRL");
+
+static DW6_SYSEX_DUMP: Shared<Vec<u8, SYSEX_LENGTH>> = Shared::uninit("DW6_SYSEX_DUMP");
+
+/// Last complete bank, from a backup or imported from a recorded backup stream, written back by restore
+static DW6_BANK: Shared<Bank> = Shared::uninit("DW6_BANK");
+
+/// Param changes waiting to be sent to the DW-6000, indexed like the dump
+static DW6_PARAMS: Shared<ParamQueue<DUMP_LENGTH>> = Shared::uninit("DW6_PARAMS");
+
+static DW6_PARAMS_PENDING: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Space out param changes so the DW-6000 can keep up with knob sweeps
+const DW6_PARAM_GAP: Duration = Duration::from_millis(15);
+
+/// Where bank backups are streamed as sysex, to be recorded off the controller
+/// Without USB there is nowhere to record them, DIN1 goes to the Beatstep
+#[cfg(feature = "usb")]
+const BANK_EXPORT_PORT: OutPort = OutPort::Usb;
+#[cfg(not(feature = "usb"))]
+const BANK_EXPORT_PORT: OutPort = OutPort::Off;
+
+/// Space out exported programs, replaying the recording gives the DW-6000 time to write each one
+const BANK_EXPORT_GAP: Duration = Duration::from_millis(200);
+
+/// CC echoing each param's panel edits until changed, in Dw6Param order
+/// Picked from CCs left undefined by the MIDI spec
+const PANEL_ECHO_CC: [u8; 35] = [
+    20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
+    102, 103, 104, 105, 106, 107, 108, 109, 110, 111, 112, 113, 114, 115, 116, 117, 118, 119,
+    85, 86, 87, 89, 90,
+];
+
+/// Bank operation requested from the Beatstep, run by the librarian task
+static DW6_BANK_OP: Signal<ThreadModeRawMutex, BankOp> = Signal::new();
+
+/// Set while the librarian walks the synth's programs
+/// Dump polling and param changes are held back, dumps received go to the librarian only
+static DW6_BUSY: AtomicBool = AtomicBool::new(false);
+
+/// Synth answered the last presence probe
+/// Dump polling and param changes are held back while offline
+static DW6_ONLINE: AtomicBool = AtomicBool::new(false);
+
+/// Channel the synth receives on, found by probing, 0 to 15
+static DW6_CHANNEL: AtomicU8 = AtomicU8::new(0);
+
+/// How often the synth's presence is checked
+const DW6_PRESENCE_PERIOD: Duration = Duration::from_millis(10000);
+
+/// How often channels are scanned while the synth is offline
+const DW6_SCAN_PERIOD: Duration = Duration::from_millis(3000);
+
+/// Synth's patch changed on our side (program change, write, resync), ask for a fresh dump
+static DW6_REFRESH: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Dump is requested this long after the last one if nothing happened
+const DW6_KEEPALIVE: Duration = Duration::from_millis(30000);
+
+/// How long to wait for the synth to answer a probe, per channel
+const DW6_PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Requests awaiting a sysex reply from the DW-6000
+static DW6_REPLY: Transaction<SYSEX_LENGTH> = Transaction::new();
+
+/// How long to wait for the synth to answer a dump request or a program write
+const DW6_REPLY_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Attempts per program before giving up on a bank operation
+const DW6_ATTEMPTS: usize = 3;
+
+#[embassy_executor::task]
+async fn bstep_rx() -> ! {
+    let mut bstep_in = MIDI_DIN_1_IN.lock().await;
+    loop {
+        if let Ok(packet) = bstep_in.get_mut().unwrap().receive().await {
+            packet_from_beatstep(packet).await;
+        }
+    }
+}
+
+#[cfg(feature = "usb")]
+#[embassy_executor::task]
+async fn usb_rx() -> ! {
+    let mut usb_in = crate::MIDI_USB_1_IN.lock().await;
+    let mut packets = [Packet::default(); 16];
+    let mut sysex: Vec<u8, SYSEX_LENGTH> = Vec::new();
+    // patch of a bank stream, waiting for the write that tells its program
+    let mut loaded = None;
+    loop {
+        if let Ok(len) = usb_in.get_mut().unwrap().read_packet(&mut packets).await {
+            for packet in &packets[..len] {
+                let Ok(msg) = MidiMessage::try_from(*packet) else {
+                    continue;
+                };
+                match capture_sysex(&mut sysex, msg) {
+                    // recorded bank backup played back, see `bank_export`
+                    Ok(SysexCapture::Captured(_)) => bank_import(&sysex, &mut loaded).await,
+                    Ok(SysexCapture::Pending(_)) => {}
+                    Err(err) => warn!("usb sysex capture error: {:?}", err),
+                    Ok(SysexCapture::NotSysex) => {
+                        // only clock, modulation sources and pedals are taken from USB host for now
+                        let mut state = DW6_CTRL.lock().await;
+                        let state = state.get_mut().unwrap();
+                        state.clock_msg(msg);
+                        state.mod_source_msg(msg);
+                        if let MidiMessage::ControlChange(_, cc, value) = msg {
+                            if let Err(err) = state.pedal(cc.0, value.0).await {
+                                error!("usb pedal {}", err);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn dw6_rx() -> ! {
+    // exclusive locked FOREVER muahahaha
+    let mut dw6_in = MIDI_DIN_2_IN.lock().await;
+    loop {
+        match dw6_in.get_mut().unwrap().receive().await {
+            Ok(packet) => packets_from_dw_6000(PacketList::single(packet)).await,
+            Err(midi_err) => error!("dw6 rx error {}", midi_err),
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn dw6_dump_request() -> ! {
+    loop {
+        // panel edits are picked up from refresh and keep-alive dumps, polling faster would slow down knob sweeps
+        let _ = with_timeout(DW6_KEEPALIVE, DW6_REFRESH.wait()).await;
+        if !dw6_ready() {
+            continue;
+        }
+        // a transaction, so probes asking for a dump don't take this reply, nor this request theirs
+        let request = || async {
+            DW6_PARAMS.lock().await.get_mut().unwrap().mark();
+            dw6_send(dw6000::dump_request_sysex(dw6_channel())).await
+        };
+        match DW6_REPLY.request(request, dw6000::DUMP_REPLY, DW6_REPLY_TIMEOUT, 1).await {
+            Ok(reply) => if let Some(dump) = reply.field(1) {
+                if let Err(err) = from_dw6000_dump(dump).await {
+                    error!("{}", err);
+                }
+            }
+            Err(err) => debug!("no dump reply {}", err),
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn mod_matrix() -> ! {
+    loop {
+        {
+            let mut state = DW6_CTRL.lock().await;
+            let state = state.get_mut().unwrap();
+            if let Some(dump) = &mut state.current_dump {
+                let roots: Vec<(Dw6Param, u8), MAX_ROUTES> = state.mod_dump.iter().take(MAX_ROUTES).map(|(p, r)| (*p, *r)).collect();
+                for (param, root) in roots {
+                    let mod_value = state.mod_matrix.modulate(param, root, &state.clock);
+                    dw6000::set_param_value(param, mod_value, dump);
+                    dw6_queue(param, dump).await;
+                }
+            }
+        }
+        Timer::after(Duration::from_millis(50)).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn dw6_param_send() -> ! {
+    loop {
+        DW6_PARAMS_PENDING.wait().await;
+        // patch is sent as a whole once the librarian is done or the synth is back
+        if !dw6_ready() {
+            continue;
+        }
+        loop {
+            let (next, gap) = {
+                let mut queue = DW6_PARAMS.lock().await;
+                let queue = queue.get_mut().unwrap();
+                (queue.pop(), queue.get_min_gap())
+            };
+            match next {
+                Some((index, value)) => {
+                    if let Err(err) = dw6_send(dw6000::set_parameter_sysex(dw6_channel(), index, value)).await {
+                        error!("dw6 param send error {}", err);
+                    }
+                    Timer::after(gap).await;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn arp_play() -> ! {
+    loop {
+        // notes are sent without holding the lock, knobs and pads are not held back by the synth's link
+        let (step, notes) = {
+            let mut state = DW6_CTRL.lock().await;
+            let state = state.get_mut().unwrap();
+            let step = state.arp.next_step();
+            let notes = step.map(|step| state.synth_note_events(step.note, step.velocity, true)).unwrap_or_default();
+            (step, notes)
+        };
+        if let Err(err) = send_notes(&notes).await {
+            error!("arp note on {}", err);
+        }
+        if let Some(step) = step {
+            Timer::after(Duration::from_millis(step.gate_ms as u64)).await;
+            let notes = DW6_CTRL.lock().await.get_mut().unwrap().synth_note_events(step.note, 0, false);
+            if let Err(err) = send_notes(&notes).await {
+                error!("arp note off {}", err);
+            }
+            Timer::after(Duration::from_millis(step.rest_ms as u64)).await;
+        } else {
+            Timer::after(ARP_IDLE_MS).await;
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn motion_play() -> ! {
+    loop {
+        {
+            let mut state = DW6_CTRL.lock().await;
+            let state = state.get_mut().unwrap();
+            if state.motion.is_playing() {
+                let ticks = state.motion_ticks(Instant::now());
+                let values = state.motion.advance(ticks);
+                if !values.is_empty() {
+                    state.set_params(&values).await;
+                }
+            }
+        }
+        Timer::after(MOTION_PERIOD).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn dw6_presence() -> ! {
+    loop {
+        if !DW6_BUSY.load(Ordering::Relaxed) {
+            let online = DW6_ONLINE.load(Ordering::Relaxed);
+            let found = if online {
+                // a single missed reply doesn't make the synth go away
+                match dw6_probe(dw6_channel()).await {
+                    Some(channel) => Some(channel),
+                    None => dw6_probe(dw6_channel()).await,
+                }
+            } else {
+                dw6_scan().await
+            };
+            match (online, found) {
+                (true, None) => {
+                    warn!("DW6000 offline");
+                    DW6_ONLINE.store(false, Ordering::Relaxed);
+                }
+                (false, Some(channel)) => {
+                    info!("DW6000 online on channel {}", channel.as_u8() + 1);
+                    DW6_CHANNEL.store(channel.as_u8(), Ordering::Relaxed);
+                    DW6_ONLINE.store(true, Ordering::Relaxed);
+                    // synth may have been power cycled, put program and edits back
+                    let (program, patch) = {
+                        let mut state = DW6_CTRL.lock().await;
+                        let state = state.get_mut().unwrap();
+                        (state.program, state.patch())
+                    };
+                    if let Err(err) = dw6_return(program, patch.as_deref()).await {
+                        error!("dw6 resync failed {}", err);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Timer::after(if DW6_ONLINE.load(Ordering::Relaxed) { DW6_PRESENCE_PERIOD } else { DW6_SCAN_PERIOD }).await;
+    }
+}
+
+/// Probe all channels until the synth answers
+async fn dw6_scan() -> Option<MidiChannel> {
+    for ch in 0..16 {
+        let channel = MidiChannel::try_from_primitive(ch).ok()?;
+        if let Some(found) = dw6_probe(channel).await {
+            return Some(found);
+        }
+    }
+    None
+}
+
+/// Ask the synth on channel for its ID, or else for a dump
+/// Synth's channel is taken from the reply
+async fn dw6_probe(channel: MidiChannel) -> Option<MidiChannel> {
+    let reply = match DW6_REPLY.request(|| dw6_send(dw6000::id_request_sysex(channel)), dw6000::ID_REPLY, DW6_PROBE_TIMEOUT, 1).await {
+        Ok(reply) => reply,
+        Err(_) => DW6_REPLY.request(|| dw6_send(dw6000::dump_request_sysex(channel)), dw6000::DUMP_REPLY, DW6_PROBE_TIMEOUT, 1).await.ok()?,
+    };
+    reply.field(0).and_then(|format_channel| dw6000::reply_channel(format_channel[0]))
+}
+
+/// Synth is there and not busy with the librarian
+fn dw6_ready() -> bool {
+    DW6_ONLINE.load(Ordering::Relaxed) && !DW6_BUSY.load(Ordering::Relaxed)
+}
+
+fn dw6_channel() -> MidiChannel {
+    MidiChannel::try_from_primitive(DW6_CHANNEL.load(Ordering::Relaxed)).unwrap_or(MidiChannel::CH1)
+}
+
+#[embassy_executor::task]
+async fn librarian() -> ! {
+    loop {
+        let op = DW6_BANK_OP.wait().await;
+        debug!("bank {} started", op);
+        let (program, patch) = {
+            let mut state = DW6_CTRL.lock().await;
+            let state = state.get_mut().unwrap();
+            // programs are changed under held notes
+            if let Err(err) = state.release_all().await {
+                error!("bank release notes failed {}", err);
+            }
+            (Some(state.program.unwrap_or(0)), state.patch())
+        };
+        DW6_BUSY.store(true, Ordering::Relaxed);
+        let result = match op {
+            BankOp::Backup => match bank_backup().await {
+                Ok(backup) => {
+                    *DW6_BANK.lock().await.get_mut().unwrap() = backup;
+                    Ok(())
+                }
+                Err(err) => Err(err),
+            },
+            BankOp::Restore => {
+                // imports keep coming in meanwhile
+                let bank = DW6_BANK.lock().await.get_mut().unwrap().clone();
+                bank_restore(&bank).await
+            }
+            BankOp::Save(target) => match &patch {
+                Some(patch) => dw6_program_write(target, patch).await,
+                None => Err(BankError::NoPatch),
+            },
+        };
+        // saved program becomes the current one, otherwise back to where we were
+        let program = match (op, &result) {
+            (BankOp::Save(target), Ok(())) => Some(target),
+            _ => program,
+        };
+        // unsaved edits included
+        if let Err(err) = dw6_return(program, patch.as_deref()).await {
+            error!("dw6 return to program {} failed {}", program, err);
+        }
+        DW6_BUSY.store(false, Ordering::Relaxed);
+        // synth is free again while the backup streams out
+        if let (BankOp::Backup, Ok(())) = (op, &result) {
+            let bank = DW6_BANK.lock().await.get_mut().unwrap().clone();
+            if let Err(err) = bank_export(&bank).await {
+                error!("bank export {}", err);
+            }
+        }
+        if let BankOp::Save(target) = op {
+            if result.is_ok() {
+                let mut state = DW6_CTRL.lock().await;
+                let state = state.get_mut().unwrap();
+                state.program = Some(target);
+                state.stored = patch;
+                state.stored_pending = false;
+                state.compare = None;
+            }
+            if let Err(err) = save_feedback(target, &result).await {
+                error!("save feedback {}", err);
+            }
+        }
+        match result {
+            Ok(()) => info!("bank {} done", op),
+            Err(err) => error!("bank {} failed {}", op, err),
+        }
+    }
+}
+
+/// Blink pads to report a program write
+/// Saved program's bank and program pads blink twice on success, all pads blink fast if memory protect is on,
+/// program pads blink slowly if the synth didn't answer
+async fn save_feedback(program: u8, result: &Result<(), BankError>) -> Result<(), MidiError> {
+    let (bank, prog) = program.div_rem(&8);
+    match result {
+        Ok(()) => bstep_blink(&[bank + 8, prog], 2, Duration::from_millis(250)).await,
+        Err(BankError::WriteRefused(_)) => bstep_blink(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15], 8, Duration::from_millis(60)).await,
+        Err(_) => bstep_blink(&[0, 1, 2, 3, 4, 5, 6, 7], 3, Duration::from_millis(400)).await,
+    }
+}
+
+/// Dump all programs from the synth's memory
+async fn bank_backup() -> Result<Bank, BankError> {
+    let mut bank = Vec::new();
+    for program in 0..PROGRAM_COUNT as u8 {
+        dw6_send(PacketList::single(program_change(dw6_channel(), program)?.into())).await?;
+        let dump = dw6_program_dump(program).await?;
+        let _ = bank.push(dump);
+    }
+    Ok(bank)
+}
+
+async fn dw6_program_dump(program: u8) -> Result<Vec<u8, DUMP_LENGTH>, BankError> {
+    let reply = DW6_REPLY.request(|| dw6_send(dw6000::dump_request_sysex(dw6_channel())), dw6000::DUMP_REPLY, DW6_REPLY_TIMEOUT, DW6_ATTEMPTS).await
+        .map_err(|err| BankError::from_transaction(err, program))?;
+    reply.field(1).and_then(|dump| Vec::from_slice(dump).ok()).ok_or(BankError::NoReply(program))
+}
+
+/// Stream a backup out as the same sysex a restore sends
+/// Playing the recording back to the DW-6000 (memory protect off) writes the whole bank
+async fn bank_export(bank: &[Vec<u8, DUMP_LENGTH>]) -> Result<(), MidiError> {
+    for (program, dump) in bank.iter().enumerate() {
+        out_send(BANK_EXPORT_PORT, dw6000::load_program_sysex(dw6_channel(), dump)).await?;
+        out_send(BANK_EXPORT_PORT, dw6000::store_program_sysex(dw6_channel(), program as u8)).await?;
+        Timer::after(BANK_EXPORT_GAP).await;
+    }
+    Ok(())
+}
+
+/// Take a program from a recorded backup stream, a patch followed by the write storing it
+/// Programs are expected in order from the first, the bank is complete after the last one
+#[cfg(feature = "usb")]
+async fn bank_import(sysex: &[u8], loaded: &mut Option<Vec<u8, DUMP_LENGTH>>) {
+    if let Some(dump) = dw6000::dump_matcher(sysex) {
+        *loaded = Vec::from_slice(dump).ok();
+        return;
+    }
+    let (Some(program), Some(dump)) = (dw6000::store_matcher(sysex), loaded.take()) else {
+        return;
+    };
+    let mut bank = DW6_BANK.lock().await;
+    let bank = bank.get_mut().unwrap();
+    // a new stream replaces the previous bank
+    if program == 0 {
+        bank.clear();
+    }
+    if program as usize != bank.len() {
+        warn!("bank import expected program {}, got {}", bank.len(), program);
+        return;
+    }
+    let _ = bank.push(dump);
+    if bank.is_full() {
+        info!("bank imported, ready to restore");
+    }
+}
+
+/// Write back all programs of a backup to the synth's memory
+async fn bank_restore(bank: &[Vec<u8, DUMP_LENGTH>]) -> Result<(), BankError> {
+    if bank.len() < PROGRAM_COUNT {
+        return Err(BankError::NoBackup);
+    }
+    for (program, dump) in bank.iter().enumerate() {
+        dw6_program_write(program as u8, dump).await?;
+    }
+    Ok(())
+}
+
+async fn dw6_program_write(program: u8, dump: &[u8]) -> Result<(), BankError> {
+    let write = || async move {
+        dw6_send(dw6000::load_program_sysex(dw6_channel(), dump)).await?;
+        dw6_send(dw6000::store_program_sysex(dw6_channel(), program)).await
+    };
+    let reply = DW6_REPLY.request(write, dw6000::WRITE_REPLY, DW6_REPLY_TIMEOUT, DW6_ATTEMPTS).await
+        .map_err(|err| BankError::from_transaction(err, program))?;
+    match reply.field(1) {
+        Some([dw6000::WRITE_OK]) => Ok(()),
+        _ => Err(BankError::WriteRefused(program)),
+    }
+}
+
+/// Select program and put edited patch back in the edit buffer
+async fn dw6_return(program: Option<u8>, patch: Option<&[u8]>) -> Result<(), MidiError> {
+    if let Some(program) = program {
+        dw6_send(PacketList::single(program_change(dw6_channel(), program)?.into())).await?;
+    }
+    let mut queue = DW6_PARAMS.lock().await;
+    let queue = queue.get_mut().unwrap();
+    queue.cancel();
+    if let Some(patch) = patch {
+        dw6_send(dw6000::load_program_sysex(dw6_channel(), patch)).await?;
+        queue.sync(patch);
+    } else {
+        queue.forget();
+    }
+    DW6_REFRESH.signal(());
+    Ok(())
+}
+
+pub async fn start_app(spawner: Spawner) -> Result<(), AppError> {
+    let seed = entropy::seed().await;
+    let mut matrix = ModMatrix::default();
+    matrix.seed(seed);
+    let mut arp = Arp::default();
+    arp.seed(seed.rotate_left(32));
+    let mut randomizer = Randomizer::default();
+    randomizer.seed(seed.rotate_left(16));
+
+    DW6_CTRL.lock().await.set(Dw6ControlInner {
+        current_dump: None,
+        mod_dump: HashMap::new(),
+        base_page: KnobPage::Osc,
+        temp_page: None,
+        bank: None,
+        mod_matrix: matrix,
+        edit_lfo: 0,
+        edit_route: 0,
+        arp,
+        clock: MidiClock::default(),
+        takeover: Takeover::default(),
+        knob_behavior: KNOB_BEHAVIOR,
+        // knobs start relative, the Takeover knob's first notch
+        ctl_knobs: HashMap::from_iter([(CtlParam::Takeover, 0)]),
+        history: History::default(),
+        snapshots: Default::default(),
+        stored: None,
+        stored_pending: true,
+        compare: None,
+        morph: None,
+        randomizer,
+        program: None,
+        save_armed: false,
+        mapping: default_mapping(),
+        learn: None,
+        macro_knobs: [0; MACROS.len()],
+        motion: Motion::default(),
+        motion_origin: Instant::now(),
+        mute_next: false,
+        velocity: Velocity::default(),
+        note_play: NotePlay::default(),
+        tracker: NoteTracker::default(),
+        panel_echo: PanelEcho::default(),
+        lock_next: false,
+    }).map_err(|_| AppError::Init)?;
+
+    DW6_SYSEX_DUMP.lock().await.set(Vec::new()).map_err(|_| AppError::Init)?;
+    DW6_BANK.lock().await.set(Vec::new()).map_err(|_| AppError::Init)?;
+    DW6_PARAMS.lock().await.set(ParamQueue::new(DW6_PARAM_GAP)).map_err(|_| AppError::Init)?;
+
+    if let Err(err) = bstep_knob_setup(KNOB_BEHAVIOR).await {
+        error!("beatstep knob setup failed {}", err);
+    }
+    if let Err(err) = bstep_button_setup().await {
+        error!("beatstep button setup failed {}", err);
+    }
+
+    spawner.spawn(bstep_rx())?;
+    spawner.spawn(dw6_rx())?;
+    spawner.spawn(mod_matrix())?;
+    spawner.spawn(dw6_dump_request())?;
+    spawner.spawn(arp_play())?;
+    spawner.spawn(motion_play())?;
+    spawner.spawn(dw6_param_send())?;
+    spawner.spawn(librarian())?;
+    spawner.spawn(dw6_presence())?;
+    #[cfg(feature = "usb")]
+    spawner.spawn(usb_rx())?;
+
+    info!("DW6000 Controller Active");
+    Ok(())
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, TryFromPrimitive)]
+#[repr(u8)]
+#[derive(defmt::Format)]
+enum KnobPage {
+    Osc = 0,
+    Env = 1,
+    Mod = 2,
+    Arp = 3,
+}
+
+impl KnobPage {
+    const ALL: [KnobPage; 4] = [KnobPage::Osc, KnobPage::Env, KnobPage::Mod, KnobPage::Arp];
+}
+
+/// What a knob controls, a DW-6000 param or one of the controller's own
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(defmt::Format)]
+enum Target {
+    Dw6(Dw6Param),
+    Ctl(CtlParam),
+    /// Index in `MACROS`
+    Macro(usize),
+}
+
+/// Macro knobs, see `DEFAULT_MAP` for their knobs
+const MACROS: &[Macro] = &[
+    // brightness: opens filter, less envelope sweep, faster attack
+    &[
+        MacroParam { param: Dw6Param::Cutoff, from: 12, to: 63 },
+        MacroParam { param: Dw6Param::VcfInt, from: 20, to: 4 },
+        MacroParam { param: Dw6Param::VcaAttack, from: 16, to: 0 },
+    ],
+    // motion: faster and deeper MG on filter, some vibrato
+    &[
+        MacroParam { param: Dw6Param::MgFreq, from: 8, to: 28 },
+        MacroParam { param: Dw6Param::MgVcf, from: 0, to: 24 },
+        MacroParam { param: Dw6Param::MgOsc, from: 0, to: 6 },
+    ],
+];
+
+impl Target {
+    /// Full range of the target, controller params take CC values as is
+    fn scale(&self) -> Scale {
+        match self {
+            Target::Dw6(param) => param.scale(),
+            Target::Ctl(_) => Scale::new(U7::MAX.0, Curve::Linear),
+            Target::Macro(_) => Scale::new(MACRO_MAX, Curve::Linear),
+        }
+    }
+}
+
+/// MIDI learn progress, each step but the first is about the knob being learned
+#[derive(Debug, Copy, Clone)]
+#[derive(defmt::Format)]
+enum Learn {
+    /// Waiting for the knob to assign
+    Knob,
+    /// Knob turns through all targets, or copies the assignment of the next other knob moved
+    Target(KnobPage, u8),
+    /// Knob sets the value at the bottom of its travel
+    Min(KnobPage, u8),
+    /// Knob sets the value at the top of its travel
+    Max(KnobPage, u8),
+    /// Knob picks the curve
+    Curve(KnobPage, u8),
+}
+
+/// Curves a learned knob can pick from
+const LEARN_CURVES: [Curve; 3] = [Curve::Linear, Curve::Exp, Curve::Stepped];
+
+/// Number of targets a learned knob turns through
+const LEARN_TARGETS: usize = Dw6Param::ALL.len() + CtlParam::ALL.len() + MACROS.len();
+
+/// Targets in the order a learned knob turns through them
+fn learn_target(idx: usize) -> Option<Target> {
+    let ctl_idx = idx.checked_sub(Dw6Param::ALL.len());
+    let macro_idx = ctl_idx.and_then(|idx| idx.checked_sub(CtlParam::ALL.len()));
+    match (ctl_idx, macro_idx) {
+        (None, _) => Some(Target::Dw6(Dw6Param::ALL[idx])),
+        (Some(ctl_idx), None) => Some(Target::Ctl(CtlParam::ALL[ctl_idx])),
+        (_, Some(macro_idx)) => (macro_idx < MACROS.len()).then_some(Target::Macro(macro_idx)),
+    }
+}
+
+fn learn_index(target: Target) -> Option<usize> {
+    (0..LEARN_TARGETS).position(|idx| learn_target(idx) == Some(target))
+}
+
+/// Pick one of `count` options from the knob being learned, relative knobs step from `current`
+fn learn_select(current: usize, count: usize, value: U7, step: Option<i8>) -> usize {
+    match step {
+        Some(step) => (current as i16 + step as i16).max(0).min(count as i16 - 1) as usize,
+        None => knob_select(value, count as u8) as usize,
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+#[derive(defmt::Format)]
+enum TogglePage {
+    Arp = 4,
+    Latch = 5,
+    Polarity = 6,
+    Chorus = 7,
+}
+
+/// Where messages meant for other gear (panel echo, bank export) are sent
+#[derive(Debug, Copy, Clone)]
+#[derive(defmt::Format)]
+enum OutPort {
+    Off,
+    /// Beatstep's DIN port
+    Din1,
+    #[cfg(feature = "usb")]
+    Usb,
+}
+
+impl OutPort {
+    #[cfg(feature = "usb")]
+    const ALL: [OutPort; 3] = [OutPort::Off, OutPort::Din1, OutPort::Usb];
+    #[cfg(not(feature = "usb"))]
+    const ALL: [OutPort; 2] = [OutPort::Off, OutPort::Din1];
+}
+
+/// Where and how edits made on the DW-6000's panel are echoed as CCs, e.g. for DAW automation
+#[derive(Debug)]
+struct PanelEcho {
+    port: OutPort,
+    channel: MidiChannel,
+    // per param, in Dw6Param order
+    cc: [u8; 35],
+    // last param edited from the panel, the one getting its CC changed
+    last: Option<Dw6Param>,
+}
+
+impl Default for PanelEcho {
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "usb")]
+            port: OutPort::Usb,
+            #[cfg(not(feature = "usb"))]
+            port: OutPort::Off,
+            channel: MidiChannel::CH1,
+            cc: PANEL_ECHO_CC,
+            last: None,
+        }
+    }
+}
+
+/// Param changed from the DW-6000's panel, found by comparing dumps
+#[derive(Debug, Copy, Clone)]
+#[derive(defmt::Format)]
+struct PanelEdit {
+    param: Dw6Param,
+    value: u8,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[derive(defmt::Format)]
+enum BankOp {
+    Backup,
+    Restore,
+    /// Write edited patch to program
+    Save(u8),
+}
+
+#[derive(Debug)]
+#[derive(defmt::Format)]
+enum BankError {
+    Midi(MidiError),
+    /// Synth didn't answer for this program, even after retries
+    NoReply(u8),
+    /// Synth refused to write this program, memory protect is probably on
+    WriteRefused(u8),
+    /// Nothing to restore, no complete backup was made since startup
+    NoBackup,
+    /// Nothing to save, no dump received from the synth yet
+    NoPatch,
+}
+
+impl From<MidiError> for BankError {
+    fn from(err: MidiError) -> Self {
+        BankError::Midi(err)
+    }
+}
+
+impl BankError {
+    fn from_transaction(err: TransactionError, program: u8) -> Self {
+        match err {
+            TransactionError::Midi(err) => BankError::Midi(err),
+            TransactionError::Timeout => BankError::NoReply(program),
+        }
+    }
+}
+
+/// Modulation destinations, in knob order
+#[derive(Debug, Copy, Clone, TryFromPrimitive)]
+#[repr(u8)]
+#[derive(defmt::Format)]
+enum ModDest {
+    Osc1Wave,
+    Osc1Level,
+    Osc1Octave,
+    Osc2Wave,
+    Osc2Level,
+    Osc2Octave,
+    Osc2Detune,
+    Interval,
+    Noise,
+    Cutoff,
+    Resonance,
+    VcfInt,
+    VcfAttack,
+    VcfDecay,
+    VcfBreak,
+    VcfSlope,
+    VcfSustain,
+    VcfRelease,
+    VcaAttack,
+    VcaDecay,
+    VcaBreak,
+    VcaSlope,
+    VcaSustain,
+    VcaRelease,
+    MgFreq,
+    MgDelay,
+    MgOsc,
+    MgVcf,
+}
+
+impl ModDest {
+    const COUNT: u8 = 28;
+}
+
+impl From<ModDest> for Dw6Param {
+    fn from(dest: ModDest) -> Self {
+        match dest {
+            ModDest::Osc1Wave => Dw6Param::Osc1Wave,
+            ModDest::Osc1Level => Dw6Param::Osc1Level,
+            ModDest::Osc1Octave => Dw6Param::Osc1Octave,
+            ModDest::Osc2Wave => Dw6Param::Osc2Wave,
+            ModDest::Osc2Level => Dw6Param::Osc2Level,
+            ModDest::Osc2Octave => Dw6Param::Osc2Octave,
+            ModDest::Osc2Detune => Dw6Param::Osc2Detune,
+            ModDest::Interval => Dw6Param::Interval,
+            ModDest::Noise => Dw6Param::Noise,
+            ModDest::Cutoff => Dw6Param::Cutoff,
+            ModDest::Resonance => Dw6Param::Resonance,
+            ModDest::VcfInt => Dw6Param::VcfInt,
+            ModDest::VcfAttack => Dw6Param::VcfAttack,
+            ModDest::VcfDecay => Dw6Param::VcfDecay,
+            ModDest::VcfBreak => Dw6Param::VcfBreak,
+            ModDest::VcfSlope => Dw6Param::VcfSlope,
+            ModDest::VcfSustain => Dw6Param::VcfSustain,
+            ModDest::VcfRelease => Dw6Param::VcfRelease,
+            ModDest::VcaAttack => Dw6Param::VcaAttack,
+            ModDest::VcaDecay => Dw6Param::VcaDecay,
+            ModDest::VcaBreak => Dw6Param::VcaBreak,
+            ModDest::VcaSlope => Dw6Param::VcaSlope,
+            ModDest::VcaSustain => Dw6Param::VcaSustain,
+            ModDest::VcaRelease => Dw6Param::VcaRelease,
+            ModDest::MgFreq => Dw6Param::MgFreq,
+            ModDest::MgDelay => Dw6Param::MgDelay,
+            ModDest::MgOsc => Dw6Param::MgOsc,
+            ModDest::MgVcf => Dw6Param::MgVcf,
+        }
+    }
+}
+
+/// DW6000 patch dump size in bytes
+const DUMP_LENGTH: usize = 26;
+
+/// DW6000 patch dump sysex size in bytes, with header
+const SYSEX_LENGTH: usize = 30;
+
+/// Dumps of all programs in the synth's memory
+type Bank = Vec<Vec<u8, DUMP_LENGTH>, PROGRAM_COUNT>;
+
+#[derive(Debug)]
+struct Dw6ControlInner {
+    current_dump: Option<Vec<u8, DUMP_LENGTH>>,
+    // saved values from dump before being modulated
+    mod_dump: HashMap<Dw6Param, u8>,
+    base_page: KnobPage,
+    // if temp_page is released quickly, is becomes base_page
+    temp_page: Option<HeldPage>,
+    bank: Option<u8>,
+    mod_matrix: ModMatrix,
+    // LFO and route currently edited from Mod page
+    edit_lfo: usize,
+    edit_route: usize,
+    arp: Arp,
+    clock: MidiClock,
+    takeover: Takeover,
+    // as set up on the Beatstep, relative or absolute
+    knob_behavior: Behavior,
+    // virtual positions of relative knobs assigned to controller params
+    ctl_knobs: HashMap<CtlParam, u8>,
+    history: History,
+    snapshots: [Option<Vec<u8, DUMP_LENGTH>>; SNAPSHOT_SLOTS],
+    // patch as stored in the synth's memory, taken from first dump after a program change
+    stored: Option<Vec<u8, DUMP_LENGTH>>,
+    stored_pending: bool,
+    // edited patch put aside while listening to the stored one
+    compare: Option<Vec<u8, DUMP_LENGTH>>,
+    // jog wheel morphs between snapshots 1 and 2 instead of setting cutoff
+    morph: Option<Morph<DUMP_LENGTH>>,
+    randomizer: Randomizer,
+    // next knob touched toggles lock of its param instead of changing it
+    lock_next: bool,
+    // last program selected from the Beatstep
+    program: Option<u8>,
+    // next bank + program pads write the edited patch instead of changing program
+    save_armed: bool,
+    mapping: Mapping<KnobPage, Target>,
+    learn: Option<Learn>,
+    // last position of each macro knob
+    macro_knobs: [u8; MACROS.len()],
+    motion: Motion,
+    // start of motion loop when following internal clock
+    motion_origin: Instant,
+    // next knob touched toggles motion mute of its param instead of changing it
+    mute_next: bool,
+    velocity: Velocity,
+    // pads and sequencer play the synth
+    note_play: NotePlay,
+    // notes sent to the synth
+    tracker: NoteTracker,
+    panel_echo: PanelEcho,
+}
+
+/// Page pad held down, some other pads act as shortcuts until it is released
+#[derive(Debug, Copy, Clone)]
+struct HeldPage {
+    page: KnobPage,
+    since: Instant,
+    // a shortcut was used, releasing the page pad won't switch page
+    combo: bool,
+}
+
+/// Shortcuts triggered by upper pads while holding a page pad
+#[derive(Debug, Copy, Clone)]
+#[derive(defmt::Format)]
+enum Combo {
+    CompareAB,
+    Morph,
+    Randomize(ParamGroup),
+    Mutate,
+    LockNext,
+    Bank(BankOp),
+    SaveArm,
+    Learn,
+    RecallSlot(usize),
+    CaptureSlot(usize),
+    MotionRecord,
+    MotionPlay,
+    MotionClear,
+    MotionMuteNext,
+    NotePlay,
+}
+
+fn page_combo(page: KnobPage, note: Note) -> Option<Combo> {
+    let pad = (note as u8).checked_sub(8).filter(|p| *p < 8)? as usize;
+    match (page, pad) {
+        (KnobPage::Osc, 0) => Some(Combo::CompareAB),
+        (KnobPage::Osc, 1) => Some(Combo::Morph),
+        (KnobPage::Osc, 2) => Some(Combo::Randomize(ParamGroup::All)),
+        (KnobPage::Osc, 3) => Some(Combo::Mutate),
+        (KnobPage::Osc, 4) => Some(Combo::Randomize(ParamGroup::Osc)),
+        (KnobPage::Osc, 5) => Some(Combo::Randomize(ParamGroup::Env)),
+        (KnobPage::Osc, 6) => Some(Combo::LockNext),
+        (KnobPage::Osc, 7) => Some(Combo::SaveArm),
+        (KnobPage::Env, slot) => Some(Combo::RecallSlot(slot)),
+        (KnobPage::Mod, slot) => Some(Combo::CaptureSlot(slot)),
+        (KnobPage::Arp, 0) => Some(Combo::MotionRecord),
+        (KnobPage::Arp, 1) => Some(Combo::MotionPlay),
+        (KnobPage::Arp, 2) => Some(Combo::MotionClear),
+        (KnobPage::Arp, 3) => Some(Combo::MotionMuteNext),
+        (KnobPage::Arp, 4) => Some(Combo::Learn),
+        (KnobPage::Arp, 5) => Some(Combo::NotePlay),
+        (KnobPage::Arp, 6) => Some(Combo::Bank(BankOp::Backup)),
+        (KnobPage::Arp, 7) => Some(Combo::Bank(BankOp::Restore)),
+        _ => None,
+    }
+}
+
+impl Dw6ControlInner {
+    /// Knob moved while learning
+    fn learn_move(&mut self, learn: Learn, page: KnobPage, cc: u8, value: U7, step: Option<i8>) {
+        let (learn_page, learn_cc) = match learn {
+            Learn::Knob => {
+                debug!("learning page {} cc {}", page, cc);
+                self.learn = Some(Learn::Target(page, cc));
+                return;
+            }
+            Learn::Target(learn_page, learn_cc) | Learn::Min(learn_page, learn_cc)
+            | Learn::Max(learn_page, learn_cc) | Learn::Curve(learn_page, learn_cc) => (learn_page, learn_cc),
+        };
+        if (learn_page, learn_cc) != (page, cc) {
+            // another knob's assignment is copied, with its range and curve
+            if let (Learn::Target(..), Some(assign)) = (learn, self.mapping.get(page, cc)) {
+                debug!("assigned page {} cc {} to {}", learn_page, learn_cc, assign.target);
+                self.mapping.set(learn_page, learn_cc, Some(assign));
+                self.learn = None;
+            }
+            return;
+        }
+        let assign = self.mapping.get(page, cc);
+        let assign = match (learn, assign) {
+            // first notch unassigns
+            (Learn::Target(..), _) => {
+                let current = assign.and_then(|a| learn_index(a.target)).map_or(0, |idx| idx + 1);
+                learn_select(current, LEARN_TARGETS + 1, value, step).checked_sub(1)
+                    .and_then(learn_target)
+                    .map(|target| Assign { target, scale: target.scale() })
+            }
+            (Learn::Min(..) | Learn::Max(..), Some(mut assign)) => {
+                // within the target's full range
+                let full = assign.target.scale();
+                let range = (full.max - full.min) as usize + 1;
+                if let Learn::Min(..) = learn {
+                    let min = learn_select(assign.scale.min.saturating_sub(full.min) as usize, range, value, step);
+                    assign.scale.min = (full.min + min as u8).min(assign.scale.max);
+                } else {
+                    let max = learn_select(assign.scale.max.saturating_sub(full.min) as usize, range, value, step);
+                    assign.scale.max = (full.min + max as u8).max(assign.scale.min);
+                }
+                Some(assign)
+            }
+            (Learn::Curve(..), Some(mut assign)) => {
+                let current = LEARN_CURVES.iter().position(|c| *c == assign.scale.curve).unwrap_or(0);
+                assign.scale.curve = LEARN_CURVES[learn_select(current, LEARN_CURVES.len(), value, step)];
+                Some(assign)
+            }
+            _ => return,
+        };
+        debug!("learn page {} cc {} {}", page, cc, assign);
+        self.mapping.set(page, cc, assign);
+    }
+
+    fn active_page(&self) -> KnobPage {
+        self.temp_page.map(|p| p.page).unwrap_or(self.base_page)
+    }
+}
+
+fn is_pad(note: Note) -> bool {
+    (note as u8) < PAD_NOTES
+}
+
+fn note_page(note: Note) -> Option<KnobPage> {
+    KnobPage::try_from(note as u8).ok()
+}
+
+fn toggle_page(note: Note) -> Option<TogglePage> {
+    TogglePage::try_from(note as u8).ok()
+}
+
+fn note_bank(note: Note) -> Option<u8> {
+    let note_u8 = note as u8;
+    match note_u8.div_rem(&8) {
+        (1, n) => Some(n),
+        _ => None,
+    }
+}
+
+fn note_prog(note: Note) -> Option<u8> {
+    let note_u8 = note as u8;
+    match note_u8.div_rem(&8) {
+        (0, n) => Some(n),
+        _ => None,
+    }
+}
+
+
+impl Dw6ControlInner {
+    /// Follow MIDI clock and transport from Beatstep or USB host
+    fn clock_msg(&mut self, msg: MidiMessage) {
+        match msg {
+            MidiMessage::TimingClock => self.clock.tick(Instant::now()),
+            MidiMessage::Start => {
+                self.clock.start();
+                self.mod_matrix.reset_lfos();
+            }
+            MidiMessage::Continue => self.clock.resume(),
+            MidiMessage::Stop => self.clock.stop(),
+            _ => {}
+        }
+    }
+
+    /// Motion loop position, from MIDI clock if running, else from internal clock at last known tempo
+    fn motion_ticks(&self, now: Instant) -> f32 {
+        if self.clock.is_running(now) {
+            self.clock.position(now)
+        } else {
+            let elapsed_ms = (now - self.motion_origin).as_micros() as f32 / 1000.0;
+            elapsed_ms * self.clock.get_bpm() * PPQN as f32 / 60_000.0
+        }
+    }
+
+    /// Follow controller sources of the mod matrix
+    fn mod_source_msg(&mut self, msg: MidiMessage) {
+        match msg {
+            MidiMessage::ControlChange(_, cc, value) if cc.0 == MOD_WHEEL_CC =>
+                self.mod_matrix.set_input(ModSource::ModWheel, value.0),
+            MidiMessage::ChannelPressure(_, pressure) | MidiMessage::NotePressure(_, _, pressure) =>
+                self.mod_matrix.set_input(ModSource::Aftertouch, pressure.0),
+            _ => {}
+        }
+    }
+
+    /// Replace route in slot, restoring params no longer modulated
+    async fn set_route(&mut self, slot: usize, route: Option<ModRoute>) {
+        if let Some(old) = self.mod_matrix.set_route(slot, route) {
+            if !self.mod_matrix.is_routed(old.dest) {
+                self.unset_modulated(old.dest).await;
+            }
+        }
+        if let Some(new) = route {
+            if !self.mod_dump.contains_key(&new.dest) {
+                if let Some(dump) = &self.current_dump {
+                    let saved_val = dw6000::get_param_value(new.dest, dump);
+                    self.set_modulated(new.dest, saved_val);
+                }
+            }
+        }
+    }
+
+    fn set_modulated(&mut self, p: Dw6Param, root_value: u8) {
+        self.mod_dump.insert(p, root_value);
+    }
+
+    async fn unset_modulated(&mut self, p: Dw6Param) {
+        if let Some(root) = self.mod_dump.remove(&p) {
+            if let Some(dump) = &mut self.current_dump {
+                dw6000::set_param_value(p, root, dump);
+                self.send_param_value(p).await
+            }
+        }
+    }
+
+    /// Current patch, with modulated params at their root value
+    fn patch(&self) -> Option<Vec<u8, DUMP_LENGTH>> {
+        let mut patch = self.current_dump.clone()?;
+        for (param, root) in &self.mod_dump {
+            dw6000::set_param_value(*param, *root, &mut patch)
+        }
+        Some(patch)
+    }
+
+    /// Send whole patch to the synth's edit buffer
+    async fn load_patch(&mut self, patch: Vec<u8, DUMP_LENGTH>) -> Result<(), MidiError> {
+        // modulated params now go around the loaded values
+        for (param, root) in self.mod_dump.iter_mut() {
+            *root = dw6000::get_param_value(*param, &patch);
+        }
+        {
+            let mut queue = DW6_PARAMS.lock().await;
+            let queue = queue.get_mut().unwrap();
+            queue.cancel();
+            queue.sync(&patch);
+        }
+        dw6_send(dw6000::load_program_sysex(dw6_channel(), &patch)).await?;
+        self.current_dump = Some(patch);
+        self.history.clear();
+        Ok(())
+    }
+
+    async fn combo(&mut self, combo: Combo) -> Result<(), MidiError> {
+        debug!("combo {}", combo);
+        match combo {
+            Combo::CompareAB => {
+                if let Some(edited) = self.compare.take() {
+                    self.load_patch(edited).await?;
+                    debug!("back to edited patch");
+                } else if let Some(stored) = self.stored.clone() {
+                    self.compare = self.patch();
+                    self.load_patch(stored).await?;
+                    debug!("comparing with stored patch");
+                }
+            }
+            Combo::Morph => {
+                if self.morph.take().is_some() {
+                    debug!("morph off");
+                } else if let (Some(a), Some(b)) = (self.snapshots[0].clone(), self.snapshots[1].clone()) {
+                    self.morph = Some(Morph::new(a, b));
+                    self.apply_morph().await;
+                    debug!("morph on");
+                } else {
+                    debug!("morph needs snapshots 1 and 2");
+                }
+            }
+            Combo::Randomize(group) => {
+                if let Some(mut patch) = self.patch() {
+                    self.randomizer.randomize(&mut patch, group);
+                    self.compare = None;
+                    self.load_patch(patch).await?;
+                }
+            }
+            Combo::Mutate => {
+                if let Some(mut patch) = self.patch() {
+                    self.randomizer.mutate(&mut patch);
+                    self.compare = None;
+                    self.load_patch(patch).await?;
+                }
+            }
+            Combo::LockNext => {
+                self.lock_next = true;
+            }
+            Combo::Bank(op) => {
+                DW6_BANK_OP.signal(op);
+            }
+            Combo::Learn => {
+                // each press goes to the next step, range and curve are skipped for unassigned knobs
+                self.learn = match self.learn {
+                    None => Some(Learn::Knob),
+                    Some(Learn::Knob) => None,
+                    Some(Learn::Target(page, cc)) => self.mapping.get(page, cc).map(|_| Learn::Min(page, cc)),
+                    Some(Learn::Min(page, cc)) => Some(Learn::Max(page, cc)),
+                    Some(Learn::Max(page, cc)) => Some(Learn::Curve(page, cc)),
+                    Some(Learn::Curve(..)) => None,
+                };
+                debug!("learn {}", self.learn);
+            }
+            Combo::SaveArm => {
+                self.save_armed = !self.save_armed;
+                debug!("save armed {}", self.save_armed);
+            }
+            Combo::RecallSlot(slot) => {
+                if let Some(patch) = self.snapshots[slot].clone() {
+                    self.compare = None;
+                    self.load_patch(patch).await?;
+                } else {
+                    debug!("snapshot slot {} empty", slot);
+                }
+            }
+            Combo::CaptureSlot(slot) => {
+                self.snapshots[slot] = self.patch();
+            }
+            Combo::MotionRecord => {
+                let recording = !self.motion.is_recording();
+                if recording && !self.motion.is_playing() {
+                    // free running loop starts now
+                    self.motion_origin = Instant::now();
+                }
+                self.motion.set_recording(recording);
+                debug!("motion recording {}", recording);
+            }
+            Combo::MotionPlay => {
+                let playing = !self.motion.is_playing();
+                if playing {
+                    self.motion_origin = Instant::now();
+                }
+                self.motion.set_playing(playing);
+                debug!("motion playing {}", playing);
+            }
+            Combo::MotionClear => {
+                self.motion.clear();
+                debug!("motion cleared");
+            }
+            Combo::MotionMuteNext => {
+                self.mute_next = true;
+            }
+            Combo::NotePlay => {
+                let enabled = !self.note_play.is_enabled();
+                if !enabled {
+                    for note in self.note_play.release_all() {
+                        self.arp.note_off(note);
+                    }
+                    self.release_all().await?;
+                }
+                self.note_play.set_enabled(enabled);
+                debug!("note play {}", enabled);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stream all param values at current morph position
+    async fn apply_morph(&mut self) {
+        if let (Some(morph), Some(dump)) = (&self.morph, &mut self.current_dump) {
+            for param in Dw6Param::ALL {
+                let value = morph.value(param);
+                if let Some(root) = self.mod_dump.get_mut(&param) {
+                    *root = value
+                } else if dw6000::get_param_value(param, dump) != value {
+                    dw6000::set_param_value(param, value, dump);
+                    dw6_queue(param, dump).await;
+                }
+            }
+        }
+    }
+
+    /// Set param value, or its root value if it is modulated
+    async fn set_param(&mut self, param: Dw6Param, value: u8) {
+        if let Some(root) = self.mod_dump.get_mut(&param) {
+            *root = value
+        } else if let Some(dump) = &mut self.current_dump {
+            dw6000::set_param_value(param, value, dump);
+            dw6_queue(param, dump).await;
+            debug!("set param {} value {}", param, value);
+        } else {
+            debug!("no dump yet");
+        }
+    }
+
+    /// Set several params at once, their changes are queued before waking the sender
+    async fn set_params(&mut self, values: &[(Dw6Param, u8)]) {
+        let Some(dump) = &mut self.current_dump else {
+            debug!("no dump yet");
+            return;
+        };
+        let mut queue = DW6_PARAMS.lock().await;
+        let queue = queue.get_mut().unwrap();
+        for (param, value) in values {
+            // modulated params move around their new root value
+            if let Some(root) = self.mod_dump.get_mut(param) {
+                *root = *value
+            } else {
+                dw6000::set_param_value(*param, *value, dump);
+                let (index, byte) = param_sysex_value(*param, dump);
+                queue.push(index, byte);
+            }
+        }
+        DW6_PARAMS_PENDING.signal(());
+    }
+
+    /// Channel notes are sent to the synth on
+    fn note_channel(&self) -> MidiChannel {
+        self.note_play.get_channel().unwrap_or_else(dw6_channel)
+    }
+
+    /// Note from the Beatstep sequencer or a play pad, to envelope and arp, and to the synth in note play mode
+    /// Key identifies the source of the note, to match its note off
+    async fn key_on(&mut self, key: u8, note: u8, velocity: u8) -> Result<(), MidiError> {
+        let play = self.note_play.is_enabled();
+        let note = if play { self.note_play.note_on(key, note) } else { note };
+        self.mod_matrix.set_input(ModSource::Velocity, velocity);
+        self.mod_matrix.env_mut().note_on(key, Instant::now());
+        self.arp.note_on(note, velocity);
+        if play && !self.arp.is_enabled() {
+            self.synth_note(note, velocity, true).await?;
+        }
+        Ok(())
+    }
+
+    async fn key_off(&mut self, key: u8) -> Result<(), MidiError> {
+        let played = self.note_play.note_off(key);
+        let note = played.unwrap_or(key);
+        self.mod_matrix.env_mut().note_off(key, Instant::now());
+        self.arp.note_off(note);
+        if played.is_some() && !self.arp.is_enabled() {
+            self.synth_note(note, 0, false).await?;
+        }
+        Ok(())
+    }
+
+    /// Send note to the synth, through the note tracker
+    async fn synth_note(&mut self, note: u8, velocity: u8, on: bool) -> Result<(), MidiError> {
+        let notes = self.synth_note_events(note, velocity, on);
+        send_notes(&notes).await
+    }
+
+    /// Notes to send for a note, for callers that send them after releasing the lock
+    fn synth_note_events(&mut self, note: u8, velocity: u8, on: bool) -> SynthNotes {
+        let channel = self.note_channel();
+        let events = if on {
+            self.tracker.note_on(channel, note, velocity)
+        } else {
+            self.tracker.note_off(channel, note)
+        };
+        self.note_offsets(&events)
+    }
+
+    /// Sustain and sostenuto pedals, applied to notes sent to the synth
+    async fn pedal(&mut self, cc: u8, value: u8) -> Result<(), MidiError> {
+        let events = self.tracker.pedal(self.note_channel(), cc, value);
+        self.play_events(&events).await
+    }
+
+    /// Turn off all notes sent to the synth, including notes held by pedals, and forget pedals
+    /// Done on page switches and program changes
+    async fn release_all(&mut self) -> Result<(), MidiError> {
+        let events = self.tracker.release_all();
+        self.play_events(&events).await
+    }
+
+    async fn play_events(&mut self, events: &[NoteEvent]) -> Result<(), MidiError> {
+        let notes = self.note_offsets(events);
+        send_notes(&notes).await
+    }
+
+    /// Velocity offset of each note event, see `velocity_note`
+    fn note_offsets(&mut self, events: &[NoteEvent]) -> SynthNotes {
+        events.iter().map(|event| (*event, self.velocity_note(event.note, event.velocity, event.on))).collect()
+    }
+
+    /// Sysex index and value moving velocity target param before a note, or restoring it after the last note
+    fn velocity_note(&mut self, note: u8, velocity: u8, on: bool) -> Option<(u8, u8)> {
+        let param = if on { self.velocity.get_target().param() } else { self.velocity.active_param() };
+        // modulated params are left to the mod matrix
+        let root = param
+            .filter(|param| !self.mod_dump.contains_key(param))
+            .and_then(|param| Some(dw6000::get_param_value(param, self.current_dump.as_ref()?)));
+        let (param, value) = if on {
+            self.velocity.note_on(note, velocity, root)?
+        } else {
+            self.velocity.note_off(note, root)?
+        };
+        // current dump keeps the patch value
+        let mut patch = self.current_dump.clone()?;
+        dw6000::set_param_value(param, value, &mut patch);
+        Some(param_sysex_value(param, &patch))
+    }
+
+    /// Add knob moves to motion loop, if recording
+    fn record_motion(&mut self, values: &[(Dw6Param, u8)]) {
+        if self.motion.is_recording() {
+            let ticks = self.motion_ticks(Instant::now());
+            for (param, value) in values {
+                if !self.motion.record(ticks, *param, *value) {
+                    warn!("motion loop full");
+                }
+            }
+        }
+    }
+
+    async fn send_param_value(&self, param: Dw6Param) {
+        if let Some(dump) = &self.current_dump {
+            dw6_queue(param, dump).await
+        }
+    }
+}
+
+
+async fn packet_from_beatstep(packet: Packet) {
+    if let Ok(msg) = MidiMessage::try_from(packet) {
+        if let Err(err) = msg_from_beatstep(msg).await {
+            error!("{}", err);
+        }
+    }
+}
+
+async fn dw6_send(packets: impl Into<PacketList>) -> Result<(), MidiError> {
+    let mut dw6_out = MIDI_DIN_2_OUT.lock().await;
+    dw6_out.get_mut().unwrap().transmit(packets.into()).await
+}
+
+/// Queue param value from dump, to be sent by dw6_param_send
+async fn dw6_queue(param: Dw6Param, dump: &[u8]) {
+    let (index, value) = param_sysex_value(param, dump);
+    DW6_PARAMS.lock().await.get_mut().unwrap().push(index, value);
+    DW6_PARAMS_PENDING.signal(());
+}
+
+#[cfg(feature = "usb")]
+async fn usb_send(packets: impl Into<PacketList>) -> Result<(), MidiError> {
+    let mut usb_out = crate::MIDI_USB_1_OUT.lock().await;
+    let usb_out = usb_out.get_mut().unwrap();
+    for packet in packets.into().0.iter() {
+        usb_out.write_packet(packet.bytes()).await.map_err(|_| MidiError::WriteError)?;
+    }
+    Ok(())
+}
+
+/// Forward panel edit as a CC, scaled to full CC range
+async fn panel_echo(echo: &PanelEcho, edit: PanelEdit) -> Result<(), MidiError> {
+    let cc = echo.cc[edit.param as usize];
+    let msg = MidiMessage::ControlChange(echo.channel, U7(cc), U7(edit.param.scale().to_cc(edit.value)));
+    out_send(echo.port, PacketList::single(msg.into())).await
+}
+
+async fn out_send(port: OutPort, packets: impl Into<PacketList>) -> Result<(), MidiError> {
+    match port {
+        OutPort::Off => Ok(()),
+        OutPort::Din1 => bstep_send(packets).await,
+        #[cfg(feature = "usb")]
+        OutPort::Usb => usb_send(packets).await,
+    }
+}
+
+async fn bstep_send(packets: impl Into<PacketList>) -> Result<(), MidiError> {
+    let mut bstep_out = MIDI_DIN_1_OUT.lock().await;
+    bstep_out.get_mut().unwrap().transmit(packets.into()).await
+}
+
+/// Light pads on and off together, pads are lit by notes matching their own
+async fn bstep_blink(pads: &[u8], times: u8, period: Duration) -> Result<(), MidiError> {
+    for _ in 0..times {
+        for pad in pads {
+            bstep_send(PacketList::single(note_on(channel(1)?, *pad, U7::MAX.0)?.into())).await?;
+        }
+        Timer::after(period / 2).await;
+        for pad in pads {
+            bstep_send(PacketList::single(note_off(channel(1)?, *pad, 0)?.into())).await?;
+        }
+        Timer::after(period / 2).await;
+    }
+    Ok(())
+}
+
+async fn bstep_config(param: beatstep::Param) -> Result<(), MidiError> {
+    for sysex in beatstep::beatstep_set(param) {
+        bstep_send(sysex).await?
+    }
+    Ok(())
+}
+
+/// Recall and Store buttons are used for undo and redo
+async fn bstep_button_setup() -> Result<(), MidiError> {
+    bstep_config(beatstep::Param::PadCC(Pad::Recall, channel(1)?, U7(UNDO_CC), U7::MAX, U7::MIN, SwitchMode::Gate)).await?;
+    bstep_config(beatstep::Param::PadCC(Pad::Store, channel(1)?, U7(REDO_CC), U7::MAX, U7::MIN, SwitchMode::Gate)).await
+}
+
+/// Knobs 1 to 16 send CC 1 to 16, jog wheel sends CC 17
+async fn bstep_knob_setup(behavior: Behavior) -> Result<(), MidiError> {
+    for knob in 0..KNOB_COUNT as u8 - 1 {
+        let encoder = Encoder::Knob(U4::try_from(knob)?);
+        bstep_config(beatstep::Param::KnobCC(encoder, channel(1)?, U7(knob + 1), U7::MIN, U7::MAX, behavior)).await?;
+    }
+    bstep_config(beatstep::Param::KnobCC(Encoder::JogWheel, channel(1)?, U7(KNOB_COUNT as u8), U7::MIN, U7::MAX, behavior)).await
+}
+
+/// Play note, with velocity offset sysex sent before note on or after note off
+/// Note events with their velocity offset, ready to send
+type SynthNotes = Vec<(NoteEvent, Option<(u8, u8)>), MAX_EVENTS>;
+
+async fn send_notes(notes: &[(NoteEvent, Option<(u8, u8)>)]) -> Result<(), MidiError> {
+    for (event, offset) in notes {
+        dw6_note(event.channel, event.note, event.velocity, event.on, *offset).await?;
+    }
+    Ok(())
+}
+
+async fn dw6_note(channel: MidiChannel, note: u8, velocity: u8, on: bool, offset: Option<(u8, u8)>) -> Result<(), MidiError> {
+    let offset = offset.filter(|_| dw6_ready());
+    if let (true, Some((index, value))) = (on, offset) {
+        dw6_offset_send(index, value).await?;
+    }
+    let msg = if on {
+        note_on(channel, note, velocity)?
+    } else {
+        note_off(channel, note, velocity)?
+    };
+    dw6_send(PacketList::single(msg.into())).await?;
+    if let (false, Some((index, value))) = (on, offset) {
+        dw6_offset_send(index, value).await?;
+    }
+    Ok(())
+}
+
+/// Velocity offset can't wait in the param queue, it has to reach the synth before the note
+/// The queue is told, so later values are compared against what the synth really has
+async fn dw6_offset_send(index: u8, value: u8) -> Result<(), MidiError> {
+    dw6_send(dw6000::set_parameter_sysex(dw6_channel(), index, value)).await?;
+    DW6_PARAMS.lock().await.get_mut().unwrap().set_sent(index, value);
+    Ok(())
+}
+
+async fn msg_from_beatstep(msg: MidiMessage) -> Result<(), MidiError> {
+    let mut state = DW6_CTRL.lock().await;
+    let state = state.get_mut().unwrap();
+    trace!("msg from beatstep {}", msg);
+    BLINK.signal(());
+    match msg {
+        MidiMessage::TimingClock | MidiMessage::Start | MidiMessage::Continue | MidiMessage::Stop => {
+            state.clock_msg(msg);
+        }
+        MidiMessage::ChannelPressure(..) | MidiMessage::NotePressure(..) => {
+            state.mod_source_msg(msg);
+        }
+        MidiMessage::ControlChange(_, cc, value) if cc.0 == UNDO_CC && value.0 > 0 => {
+            let edits = state.history.undo();
+            debug!("undo {}", edits.as_slice());
+            let values: Vec<(Dw6Param, u8), GROUP_LEN> = edits.iter().map(|edit| (edit.param, edit.old)).collect();
+            state.set_params(&values).await;
+        }
+        MidiMessage::ControlChange(_, cc, value) if cc.0 == REDO_CC && value.0 > 0 => {
+            let edits = state.history.redo();
+            debug!("redo {}", edits.as_slice());
+            let values: Vec<(Dw6Param, u8), GROUP_LEN> = edits.iter().map(|edit| (edit.param, edit.new)).collect();
+            state.set_params(&values).await;
+        }
+        // button release
+        MidiMessage::ControlChange(_, cc, _) if cc.0 == UNDO_CC || cc.0 == REDO_CC => {}
+        MidiMessage::ControlChange(_, cc, value) if cc.0 == SUSTAIN_CC || cc.0 == SOSTENUTO_CC => {
+            return state.pedal(cc.0, value.0).await;
+        }
+        MidiMessage::NoteOn(_, note, velocity) if !is_pad(note) => {
+            return state.key_on(note as u8, note as u8, velocity.0).await;
+        }
+        MidiMessage::NoteOff(_, note, _) if !is_pad(note) => {
+            return state.key_off(note as u8).await;
+        }
+        MidiMessage::NoteOn(_, note, velocity) => {
+            if let Some(held) = &mut state.temp_page {
+                if let Some(combo) = page_combo(held.page, note) {
+                    held.combo = true;
+                    return state.combo(combo).await;
+                }
+            }
+            // bank pads play notes, page and toggle pads keep working
+            if let (true, Some(pad)) = (state.note_play.is_enabled(), note_bank(note)) {
+                let pad_note = state.note_play.pad_note(pad);
+                return state.key_on(note as u8, pad_note, velocity.0).await;
+            }
+            if let Some(bank) = note_bank(note) {
+                debug!("selected bank {}", bank);
+                state.bank = Some(bank)
+            } else if let Some(prog) = note_prog(note) {
+                debug!("selected prog {}", prog);
+                if DW6_BUSY.load(Ordering::Relaxed) {
+                    debug!("librarian busy");
+                } else if let Some(bank) = state.bank {
+                    let program_num = (bank * 8) + prog;
+                    if state.save_armed {
+                        state.save_armed = false;
+                        debug!("saving to program {}", program_num);
+                        DW6_BANK_OP.signal(BankOp::Save(program_num));
+                        return Ok(());
+                    }
+                    // notes and pedals don't carry over to the new patch
+                    state.release_all().await?;
+                    let pc = program_change(dw6_channel(), program_num)?;
+                    dw6_send(PacketList::single(pc.into())).await?;
+                    state.program = Some(program_num);
+                    {
+                        let mut queue = DW6_PARAMS.lock().await;
+                        let queue = queue.get_mut().unwrap();
+                        queue.cancel();
+                        queue.forget();
+                    }
+                    // edits were made to another patch
+                    state.history.clear();
+                    state.takeover.release_all();
+                    state.compare = None;
+                    state.stored_pending = true;
+                    DW6_REFRESH.signal(());
+                    debug!("program changed to {}", program_num);
+                    return Ok(());
+                } else {
+                    debug!("no bank selected");
+                }
+            }
+            if let Some(page) = note_page(note) {
+                debug!("selected temp page {:?}", page);
+                state.temp_page = Some(HeldPage { page, since: Instant::now(), combo: false });
+                state.release_all().await?;
+                return Ok(());
+            }
+            if let Some(tog) = toggle_page(note) {
+                debug!("toggled {}", tog);
+                match tog {
+                    TogglePage::Arp => {
+                        let enabled = !state.arp.is_enabled();
+                        state.arp.set_enabled(enabled);
+                        // notes played directly or by the arp are not followed by the other
+                        state.release_all().await?;
+                        debug!("arp enabled {}", enabled);
+                    }
+                    TogglePage::Latch => {
+                        let latch = !state.arp.is_latch();
+                        state.arp.set_latch(latch);
+                        debug!("arp latch {}", latch);
+                    }
+                    TogglePage::Polarity | TogglePage::Chorus => {
+                        if let Some(dump) = &state.current_dump {
+                            let param = if tog == TogglePage::Polarity { Dw6Param::Polarity } else { Dw6Param::Chorus };
+                            let old = dw6000::get_param_value(param, dump);
+                            state.history.record(Gesture::Press, param, old, old ^ 1, Instant::now());
+                            state.set_param(param, old ^ 1).await;
+                        } else {
+                            debug!("no dump yet");
+                        }
+                    }
+                }
+                return Ok(());
+            }
+        }
+        MidiMessage::NoteOff(_, note, _) => {
+            if state.note_play.is_enabled() && note_bank(note).is_some() {
+                return state.key_off(note as u8).await;
+            }
+            if state.bank == note_bank(note) {
+                debug!("unselected bank");
+                state.bank = None
+            }
+            if let Some(held) = state.temp_page {
+                if let Some(note_page) = note_page(note) {
+                    if note_page == held.page {
+                        let held_for_ms = Instant::now() - held.since;
+                        if held_for_ms < SHORT_PRESS_MS && !held.combo {
+                            debug!("selected base page {}", held.page);
+                            state.base_page = held.page;
+                            state.release_all().await?;
+                        }
+                        state.temp_page = None;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        MidiMessage::ControlChange(_ch, cc, value) => {
+            let knob = (u8::from(cc) as usize).wrapping_sub(1);
+            let step = if knob < KNOB_COUNT { state.knob_behavior.step(value) } else { None };
+            if let Some(morph) = &mut state.morph {
+                if knob == JOG_WHEEL {
+                    match step {
+                        Some(step) => morph.step(step),
+                        None => morph.set_position(value.0),
+                    }
+                    trace!("morph position {}", morph.get_position());
+                    state.apply_morph().await;
+                    return Ok(());
+                }
+            }
+            let page = state.active_page();
+            if let Some(learn) = state.learn {
+                state.learn_move(learn, page, cc.0, value, step);
+                return Ok(());
+            }
+            let assign = state.mapping.get(page, cc.0);
+            if let Some(Assign { target: Target::Dw6(param), scale }) = assign {
+                if state.lock_next {
+                    let locked = !state.randomizer.is_locked(param);
+                    state.randomizer.set_locked(param, locked);
+                    state.lock_next = false;
+                    debug!("param {} locked {}", param, locked);
+                    return Ok(());
+                }
+                if state.mute_next {
+                    let muted = !state.motion.is_muted(param);
+                    state.motion.set_muted(param, muted);
+                    state.mute_next = false;
+                    debug!("param {} motion muted {}", param, muted);
+                    return Ok(());
+                }
+                // modulated params are compared to their root value
+                let reference = state.mod_dump.get(&param).copied()
+                    .or_else(|| state.current_dump.as_ref().map(|dump| dw6000::get_param_value(param, dump)));
+                let value = match (step, reference) {
+                    (Some(step), Some(reference)) => state.takeover.relative(knob, step, reference, &scale),
+                    (Some(_), None) => {
+                        debug!("no dump yet");
+                        return Ok(());
+                    }
+                    (None, Some(reference)) => match state.takeover.update(knob, value.0, reference, &scale) {
+                        Some(value) => value,
+                        None => {
+                            trace!("knob {} not picked up {} <> {}", knob + 1, value.0, scale.to_cc(reference));
+                            return Ok(());
+                        }
+                    },
+                    (None, None) => scale.from_cc(value.0),
+                };
+                if let Some(reference) = reference {
+                    state.history.record(Gesture::Knob(cc.0), param, reference, value, Instant::now());
+                }
+                state.record_motion(&[(param, value)]);
+                state.set_param(param, value).await;
+                return Ok(());
+            } else if let Some(Assign { target: Target::Macro(idx), scale }) = assign {
+                let Some(position) = state.macro_knobs.get_mut(idx) else {
+                    return Ok(());
+                };
+                // macros have no patch value to pick up, absolute knobs jump
+                *position = match step {
+                    Some(step) => (*position as i16 + step as i16).max(0).min(MACRO_MAX as i16) as u8,
+                    None => scale.from_cc(value.0),
+                };
+                let position = *position;
+                let values: Vec<(Dw6Param, u8), 8> = MACROS[idx].iter().map(|leg| (leg.param, leg.value(position))).collect();
+                trace!("macro {} position {}", idx + 1, position);
+                let now = Instant::now();
+                for (param, value) in values.iter().copied() {
+                    // modulated params are compared to their root value
+                    let reference = state.mod_dump.get(&param).copied()
+                        .or_else(|| state.current_dump.as_ref().map(|dump| dw6000::get_param_value(param, dump)));
+                    if let Some(reference) = reference {
+                        state.history.record(Gesture::Knob(cc.0), param, reference, value, now);
+                    }
+                    // knobs of params moved by the macro have to pick them up again
+                    if let Some(knob) = dw_param_knob(&state.mapping, param, page) {
+                        state.takeover.release(knob);
+                    }
+                }
+                state.record_motion(&values);
+                state.set_params(&values).await;
+                return Ok(());
+            } else if let Some(Assign { target: Target::Ctl(param), scale }) = assign {
+                let value = match step {
+                    Some(step) => {
+                        let position = state.ctl_knobs.entry(param).or_insert(U7::MAX.0 / 2 + 1);
+                        *position = (*position as i16 + step as i16).max(0).min(U7::MAX.0 as i16) as u8;
+                        U7(*position)
+                    }
+                    None => value,
+                };
+                let value = U7(scale.from_cc(value.0));
+                match param {
+                    CtlParam::LfoSelect => {
+                        state.edit_lfo = knob_select(value, LFO_COUNT as u8) as usize;
+                        debug!("editing lfo {}", state.edit_lfo + 1);
+                    }
+                    CtlParam::LfoRate => {
+                        let base_rate = (value.0 as f32 + 1.0) * 0.1;
+                        debug!("ratev {} ratex {}", value.0, base_rate);
+                        state.mod_matrix.lfo_mut(state.edit_lfo).set_rate_hz(base_rate.min(40.0).max(0.03));
+                    }
+                    CtlParam::LfoWave => {
+                        state.mod_matrix.lfo_mut(state.edit_lfo).set_waveform(Waveform::from(knob_select(value, Waveform::COUNT)));
+                    }
+                    CtlParam::LfoSync => {
+                        // first notch is free running, then all divisions from shortest to longest
+                        let sync = match knob_select(value, Division::COUNT + 1) {
+                            0 => None,
+                            div => Some(Division::from(div - 1)),
+                        };
+                        state.mod_matrix.lfo_mut(state.edit_lfo).set_sync(sync);
+                        debug!("lfo {} sync {:?}", state.edit_lfo + 1, sync);
+                    }
+                    CtlParam::LfoAmount => {
+                        state.mod_matrix.lfo_mut(state.edit_lfo).set_amount(f32::from(value.0) / f32::from(U7::MAX.0));
+                        debug!("lfo {} amount {}", state.edit_lfo + 1, state.mod_matrix.lfo_mut(state.edit_lfo).get_amount());
+                    }
+                    CtlParam::RouteSlot => {
+                        state.edit_route = knob_select(value, MAX_ROUTES as u8) as usize;
+                        // LFO knobs follow the selected route's LFO
+                        if let Some(lfo) = state.mod_matrix.get_route(state.edit_route).and_then(|r| r.source.lfo()) {
+                            state.edit_lfo = lfo;
+                        }
+                        debug!("editing route {} {:?}", state.edit_route + 1, state.mod_matrix.get_route(state.edit_route));
+                    }
+                    CtlParam::RouteSource => {
+                        // first notch removes the route
+                        let slot = state.edit_route;
+                        let route = match knob_select(value, ModSource::COUNT + 1) {
+                            0 => None,
+                            src => Some(ModRoute {
+                                source: ModSource::from(src - 1),
+                                ..state.mod_matrix.get_route(slot).unwrap_or(NEW_ROUTE)
+                            }),
+                        };
+                        if let Some(lfo) = route.and_then(|r| r.source.lfo()) {
+                            state.edit_lfo = lfo;
+                        }
+                        state.set_route(slot, route).await;
+                    }
+                    CtlParam::RouteDest => {
+                        let slot = state.edit_route;
+                        if let Ok(dest) = ModDest::try_from(knob_select(value, ModDest::COUNT)) {
+                            let route = ModRoute {
+                                dest: dest.into(),
+                                ..state.mod_matrix.get_route(slot).unwrap_or(NEW_ROUTE)
+                            };
+                            state.set_route(slot, Some(route)).await;
+                        }
+                    }
+                    CtlParam::RouteDepth => {
+                        // centered knob is zero depth
+                        let slot = state.edit_route;
+                        if let Some(mut route) = state.mod_matrix.get_route(slot) {
+                            route.depth = ((value.0 as f32 - 64.0) / 63.0).max(-1.0).min(1.0);
+                            state.mod_matrix.set_route(slot, Some(route));
+                        }
+                    }
+                    CtlParam::ArpRate => {
+                        // from 1 step per second up to 32 steps per second
+                        let step_ms = 1000 - (value.0 as u32 * 969 / U7::MAX.0 as u32);
+                        state.arp.set_step_ms(step_ms);
+                        debug!("arp step {}ms", step_ms);
+                    }
+                    CtlParam::ArpGate => {
+                        state.arp.set_gate(f32::from(value.0) / f32::from(U7::MAX.0));
+                    }
+                    CtlParam::ArpOctaves => {
+                        state.arp.set_octaves(value.0 / 32 + 1);
+                        debug!("arp octaves {}", state.arp.get_octaves());
+                    }
+                    CtlParam::ArpOrder => {
+                        state.arp.set_mode(ArpMode::from(value.0 / 26));
+                        debug!("arp mode {:?}", state.arp.get_mode());
+                    }
+                    CtlParam::EnvDelay => {
+                        state.mod_matrix.env_mut().set_delay_ms(env_time_ms(value));
+                    }
+                    CtlParam::EnvAttack => {
+                        state.mod_matrix.env_mut().set_attack_ms(env_time_ms(value));
+                    }
+                    CtlParam::EnvDecay => {
+                        state.mod_matrix.env_mut().set_decay_ms(env_time_ms(value));
+                    }
+                    CtlParam::EnvSustain => {
+                        state.mod_matrix.env_mut().set_sustain(f32::from(value.0) / f32::from(U7::MAX.0));
+                    }
+                    CtlParam::EnvRelease => {
+                        state.mod_matrix.env_mut().set_release_ms(env_time_ms(value));
+                    }
+                    CtlParam::MotionLength => {
+                        let length = MOTION_LENGTHS[knob_select(value, MOTION_LENGTHS.len() as u8) as usize];
+                        state.motion.set_length(length);
+                        debug!("motion length {:?}", length);
+                    }
+                    CtlParam::VelocityTarget => {
+                        state.velocity.set_target(VelocityTarget::from(knob_select(value, VelocityTarget::COUNT)));
+                        debug!("velocity target {:?}", state.velocity.get_target());
+                    }
+                    CtlParam::VelocityDepth => {
+                        state.velocity.set_depth(f32::from(value.0) / f32::from(U7::MAX.0));
+                    }
+                    CtlParam::VelocityCurve => {
+                        state.velocity.set_curve(VelocityCurve::from(knob_select(value, VelocityCurve::COUNT)));
+                        debug!("velocity curve {:?}", state.velocity.get_curve());
+                    }
+                    CtlParam::PlayTranspose => {
+                        let transpose = knob_select(value, MAX_TRANSPOSE as u8 * 2 + 1) as i8 - MAX_TRANSPOSE;
+                        state.note_play.set_transpose(transpose);
+                        debug!("note play transpose {}", transpose);
+                    }
+                    CtlParam::PlayScale => {
+                        state.note_play.set_scale(SeqScale::from(knob_select(value, SeqScale::COUNT)));
+                        debug!("note play scale {:?}", state.note_play.get_scale());
+                    }
+                    CtlParam::PlayChannel => {
+                        // first notch follows the synth's channel
+                        let channel = match knob_select(value, 17) {
+                            0 => None,
+                            ch => MidiChannel::try_from_primitive(ch - 1).ok(),
+                        };
+                        if channel.map(|ch| ch as u8) != state.note_play.get_channel().map(|ch| ch as u8) {
+                            // notes on the previous channel would never get their note off
+                            state.release_all().await?;
+                            state.note_play.set_channel(channel);
+                            debug!("note play channel {:?}", channel);
+                        }
+                    }
+                    CtlParam::VoicePriority => {
+                        let priority = Priority::from(knob_select(value, Priority::COUNT));
+                        if priority != state.tracker.get_priority() {
+                            let events = state.tracker.set_priority(priority);
+                            state.play_events(&events).await?;
+                            debug!("voice priority {:?}", priority);
+                        }
+                    }
+                    CtlParam::Legato => {
+                        state.tracker.set_legato(value.0 > U7::MAX.0 / 2);
+                    }
+                    CtlParam::MutateAmount => {
+                        state.randomizer.set_amount(f32::from(value.0) / f32::from(U7::MAX.0));
+                    }
+                    CtlParam::Takeover => {
+                        // first notch sets knobs relative, others absolute with a takeover mode
+                        let mode = knob_select(value, TakeoverMode::COUNT + 1).checked_sub(1).map(TakeoverMode::from);
+                        if let Some(mode) = mode {
+                            // jog wheel keeps jumping, it has no absolute position
+                            for knob in 0..KNOB_COUNT - 1 {
+                                state.takeover.set_mode(knob, mode);
+                            }
+                        }
+                        let behavior = if mode.is_some() { Behavior::Absolute } else { KNOB_BEHAVIOR };
+                        if behavior != state.knob_behavior {
+                            state.knob_behavior = behavior;
+                            // absolute knobs are anywhere, they have to pick up patch values
+                            state.takeover.release_all();
+                            bstep_knob_setup(behavior).await?;
+                        }
+                        debug!("knob takeover {:?}", mode);
+                    }
+                    CtlParam::EchoPort => {
+                        state.panel_echo.port = OutPort::ALL[knob_select(value, OutPort::ALL.len() as u8) as usize];
+                        debug!("panel echo port {}", state.panel_echo.port);
+                    }
+                    CtlParam::EchoChannel => {
+                        if let Ok(channel) = MidiChannel::try_from_primitive(knob_select(value, 16)) {
+                            state.panel_echo.channel = channel;
+                        }
+                    }
+                    CtlParam::EchoCc => {
+                        // no panel edit yet, nothing to change
+                        if let Some(param) = state.panel_echo.last {
+                            state.panel_echo.cc[param as usize] = value.0;
+                            debug!("panel echo {} on cc {}", param, value.0);
+                        }
+                    }
+                }
+                return Ok(())
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(defmt::Format)]
+enum CtlParam {
+    LfoSelect,
+    LfoRate,
+    LfoWave,
+    LfoSync,
+    LfoAmount,
+    RouteSlot,
+    RouteSource,
+    RouteDest,
+    RouteDepth,
+    ArpRate,
+    ArpGate,
+    ArpOctaves,
+    ArpOrder,
+    EnvDelay,
+    EnvAttack,
+    EnvDecay,
+    EnvSustain,
+    EnvRelease,
+    MutateAmount,
+    MotionLength,
+    VelocityTarget,
+    VelocityDepth,
+    VelocityCurve,
+    PlayTranspose,
+    PlayScale,
+    PlayChannel,
+    VoicePriority,
+    Legato,
+    Takeover,
+    EchoPort,
+    EchoChannel,
+    EchoCc,
+}
+
+impl CtlParam {
+    /// In the order a learned knob turns through them
+    const ALL: [CtlParam; 32] = [
+        CtlParam::LfoSelect, CtlParam::LfoRate, CtlParam::LfoWave, CtlParam::LfoSync, CtlParam::LfoAmount,
+        CtlParam::RouteSlot, CtlParam::RouteSource, CtlParam::RouteDest, CtlParam::RouteDepth,
+        CtlParam::ArpRate, CtlParam::ArpGate, CtlParam::ArpOctaves, CtlParam::ArpOrder,
+        CtlParam::EnvDelay, CtlParam::EnvAttack, CtlParam::EnvDecay, CtlParam::EnvSustain, CtlParam::EnvRelease,
+        CtlParam::MutateAmount, CtlParam::MotionLength,
+        CtlParam::VelocityTarget, CtlParam::VelocityDepth, CtlParam::VelocityCurve,
+        CtlParam::PlayTranspose, CtlParam::PlayScale, CtlParam::PlayChannel,
+        CtlParam::VoicePriority, CtlParam::Legato, CtlParam::Takeover,
+        CtlParam::EchoPort, CtlParam::EchoChannel, CtlParam::EchoCc,
+    ];
+}
+
+/// Route created when editing an empty slot
+const NEW_ROUTE: ModRoute = ModRoute {
+    source: ModSource::Lfo1,
+    dest: Dw6Param::Cutoff,
+    depth: 0.0,
+};
+
+/// Split knob travel in `count` equal notches
+fn knob_select(value: U7, count: u8) -> u8 {
+    (value.0 as u16 * count as u16 / (U7::MAX.0 as u16 + 1)) as u8
+}
+
+/// Longest envelope stage
+const ENV_MAX_MS: u32 = 5000;
+
+/// Squared knob travel, for finer control of short times
+fn env_time_ms(value: U7) -> u32 {
+    let max = U7::MAX.0 as u32;
+    value.0 as u32 * value.0 as u32 * ENV_MAX_MS / (max * max)
+}
+
+async fn packets_from_dw_6000(packets: PacketList) {
+    for packet in packets.0.into_iter() {
+        let mut buffer = DW6_SYSEX_DUMP.lock().await;
+        if let Ok(msg) = MidiMessage::try_from(packet) {
+            trace!("message from DW6000 {}", msg);
+            BLINK.signal(());
+            match capture_sysex(buffer.get_mut().unwrap(), msg) {
+                Ok(SysexCapture::Captured(len)) => {
+                    let sysex = buffer.get().unwrap().as_slice();
+                    if DW6_REPLY.offer(sysex) {
+                        trace!("reply from DW6000 {=[u8]:x}", sysex);
+                    } else if let Some(dump) = dw6000::dump_matcher(sysex) {
+                        if !dw6_ready() {
+                            // late reply to a probe or the librarian, maybe for some other channel or program
+                            debug!("dump ignored, synth offline or librarian busy");
+                        } else if let Err(err) = from_dw6000_dump(dump).await {
+                            error!("{}", err);
+                        }
+                    } else {
+                        debug!("unhandled sysex from DW6000 {=[u8]:x}", sysex);
+                    }
+                }
+                Ok(SysexCapture::Pending(len)) => {}
+                #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+                Err(err) => warn!("sysex capture error: {:?}", err),
+                Ok(SysexCapture::NotSysex) => {
+                    info!("from DW6000: {}", msg);
+                    DW6_CTRL.lock().await.get_mut().unwrap().mod_source_msg(msg);
+                }
+            }
+        }
+    }
+}
+
+async fn from_dw6000_dump(dump: &[u8]) -> Result<bool, MidiError> {
+    let mut state = DW6_CTRL.lock().await;
+    let state = state.get_mut().unwrap();
+    // if let Some(mut dump) = ctx.tags.remove(&Tag::Dump(26)) {
+    let mut dump: Vec<u8, DUMP_LENGTH> = Vec::from_slice(dump).unwrap();
+    {
+        let mut queue = DW6_PARAMS.lock().await;
+        let queue = queue.get_mut().unwrap();
+        // dump may be older than the last edits
+        queue.merge(&mut dump, &Dw6Param::ALL);
+        // synth has these values now, no need to send them again
+        queue.sync(&dump);
+    }
+    // rewrite original values before they were modulated
+    for s in &state.mod_dump {
+        dw6000::set_param_value(*s.0, *s.1, &mut dump)
+    }
+    // velocity offset of held notes is not part of the patch
+    if let (Some(param), Some(previous)) = (state.velocity.active_param(), &state.current_dump) {
+        dw6000::set_param_value(param, dw6000::get_param_value(param, previous), &mut dump)
+    }
+    // routes set before the first dump have no root value yet
+    let routed: Vec<Dw6Param, MAX_ROUTES> = state.mod_matrix.routed().collect();
+    for param in routed {
+        if !state.mod_dump.contains_key(&param) {
+            state.set_modulated(param, dw6000::get_param_value(param, &dump));
+        }
+    }
+    // first dump after a program change is a whole new patch, not an edit
+    if !state.stored_pending {
+        if let Some(previous) = &state.current_dump {
+            let edits: Vec<PanelEdit, 35> = Dw6Param::ALL.into_iter()
+                // modulated params change all the time
+                .filter(|param| !state.mod_dump.contains_key(param))
+                .map(|param| PanelEdit { param, value: dw6000::get_param_value(param, &dump) })
+                .filter(|edit| dw6000::get_param_value(edit.param, previous) != edit.value)
+                .collect();
+            for edit in edits {
+                debug!("panel edit {}", edit);
+                if let Some(knob) = dw_param_knob(&state.mapping, edit.param, state.active_page()) {
+                    state.takeover.release(knob);
+                }
+                state.panel_echo.last = Some(edit.param);
+                if let Err(err) = panel_echo(&state.panel_echo, edit).await {
+                    error!("panel echo {}", err);
+                }
+            }
+        }
+    }
+    state.current_dump = Some(dump);
+    if state.stored_pending {
+        state.stored = state.patch();
+        state.stored_pending = false;
+    }
+    Ok(false)
+}
+
+/// Sysex param number and raw byte holding that param's value
+fn param_sysex_value(param: Dw6Param, dump_buf: &[u8]) -> (u8, u8) {
+    let index = param.index();
+    (index, dump_buf[index as usize])
+}
+
+/// Knob currently controlling param, if any
+fn dw_param_knob(mapping: &Mapping<KnobPage, Target>, param: Dw6Param, page: KnobPage) -> Option<usize> {
+    mapping.find(page, Target::Dw6(param)).and_then(|cc| (cc as usize).checked_sub(1))
+}
+
+/// Knob assignments on startup, for the CCs set up by `bstep_knob_setup`
+/// Entries without a page apply to all pages
+const DEFAULT_MAP: &[(Option<KnobPage>, u8, Target)] = &[
+    // jogwheel hardwired to cutoff for her pleasure
+    (None, 17, Target::Dw6(Dw6Param::Cutoff)),
+    (None, 8, Target::Dw6(Dw6Param::Resonance)),
+    // AssignMode => defined on DW6000 panel
+    (None, 18, Target::Dw6(Dw6Param::Polarity)),
+    (None, 19, Target::Dw6(Dw6Param::Chorus)),
+
+    (Some(KnobPage::Osc), 1, Target::Dw6(Dw6Param::Osc1Level)),
+    (Some(KnobPage::Osc), 2, Target::Dw6(Dw6Param::Osc1Octave)),
+    (Some(KnobPage::Osc), 3, Target::Dw6(Dw6Param::Osc1Wave)),
+    (Some(KnobPage::Osc), 4, Target::Dw6(Dw6Param::Noise)),
+    (Some(KnobPage::Osc), 5, Target::Dw6(Dw6Param::BendOsc)),
+    (Some(KnobPage::Osc), 6, Target::Dw6(Dw6Param::BendVcf)),
+    (Some(KnobPage::Osc), 7, Target::Dw6(Dw6Param::Portamento)),
+    (Some(KnobPage::Osc), 9, Target::Dw6(Dw6Param::Osc2Level)),
+    (Some(KnobPage::Osc), 10, Target::Dw6(Dw6Param::Osc2Octave)),
+    (Some(KnobPage::Osc), 11, Target::Dw6(Dw6Param::Osc2Wave)),
+    (Some(KnobPage::Osc), 12, Target::Dw6(Dw6Param::Interval)),
+    (Some(KnobPage::Osc), 13, Target::Dw6(Dw6Param::Osc2Detune)),
+    (Some(KnobPage::Osc), 14, Target::Ctl(CtlParam::PlayTranspose)),
+    (Some(KnobPage::Osc), 15, Target::Macro(0)),
+    (Some(KnobPage::Osc), 16, Target::Macro(1)),
+
+    (Some(KnobPage::Env), 1, Target::Dw6(Dw6Param::VcaAttack)),
+    (Some(KnobPage::Env), 2, Target::Dw6(Dw6Param::VcaDecay)),
+    (Some(KnobPage::Env), 3, Target::Dw6(Dw6Param::VcaBreak)),
+    (Some(KnobPage::Env), 4, Target::Dw6(Dw6Param::VcaSustain)),
+    (Some(KnobPage::Env), 5, Target::Dw6(Dw6Param::VcaSlope)),
+    (Some(KnobPage::Env), 6, Target::Dw6(Dw6Param::VcaRelease)),
+    (Some(KnobPage::Env), 7, Target::Ctl(CtlParam::PlayChannel)),
+    (Some(KnobPage::Env), 9, Target::Dw6(Dw6Param::VcfAttack)),
+    (Some(KnobPage::Env), 10, Target::Dw6(Dw6Param::VcfDecay)),
+    (Some(KnobPage::Env), 11, Target::Dw6(Dw6Param::VcfBreak)),
+    (Some(KnobPage::Env), 12, Target::Dw6(Dw6Param::VcfSustain)),
+    (Some(KnobPage::Env), 13, Target::Dw6(Dw6Param::VcfSlope)),
+    (Some(KnobPage::Env), 14, Target::Dw6(Dw6Param::VcfRelease)),
+    (Some(KnobPage::Env), 15, Target::Dw6(Dw6Param::VcfInt)),
+    (Some(KnobPage::Env), 16, Target::Dw6(Dw6Param::KbdTrack)),
+
+    (Some(KnobPage::Mod), 1, Target::Dw6(Dw6Param::MgFreq)),
+    (Some(KnobPage::Mod), 2, Target::Dw6(Dw6Param::MgDelay)),
+    (Some(KnobPage::Mod), 3, Target::Dw6(Dw6Param::MgOsc)),
+    (Some(KnobPage::Mod), 4, Target::Dw6(Dw6Param::MgVcf)),
+    (Some(KnobPage::Mod), 5, Target::Dw6(Dw6Param::BendOsc)),
+    (Some(KnobPage::Mod), 6, Target::Dw6(Dw6Param::BendVcf)),
+    (Some(KnobPage::Mod), 7, Target::Dw6(Dw6Param::Portamento)),
+    (Some(KnobPage::Mod), 9, Target::Ctl(CtlParam::LfoAmount)),
+    (Some(KnobPage::Mod), 10, Target::Ctl(CtlParam::LfoRate)),
+    (Some(KnobPage::Mod), 11, Target::Ctl(CtlParam::LfoWave)),
+    (Some(KnobPage::Mod), 12, Target::Ctl(CtlParam::LfoSync)),
+    (Some(KnobPage::Mod), 13, Target::Ctl(CtlParam::RouteSlot)),
+    (Some(KnobPage::Mod), 14, Target::Ctl(CtlParam::RouteSource)),
+    (Some(KnobPage::Mod), 15, Target::Ctl(CtlParam::RouteDest)),
+    (Some(KnobPage::Mod), 16, Target::Ctl(CtlParam::RouteDepth)),
+
+    (Some(KnobPage::Arp), 1, Target::Ctl(CtlParam::ArpRate)),
+    (Some(KnobPage::Arp), 2, Target::Ctl(CtlParam::ArpGate)),
+    (Some(KnobPage::Arp), 3, Target::Ctl(CtlParam::ArpOctaves)),
+    (Some(KnobPage::Arp), 4, Target::Ctl(CtlParam::ArpOrder)),
+    // motion length, voice priority and legato are left to MIDI learn
+    (Some(KnobPage::Arp), 5, Target::Ctl(CtlParam::VelocityTarget)),
+    (Some(KnobPage::Arp), 6, Target::Ctl(CtlParam::VelocityDepth)),
+    (Some(KnobPage::Arp), 7, Target::Ctl(CtlParam::VelocityCurve)),
+    (Some(KnobPage::Arp), 9, Target::Ctl(CtlParam::EnvDelay)),
+    (Some(KnobPage::Arp), 10, Target::Ctl(CtlParam::EnvAttack)),
+    (Some(KnobPage::Arp), 11, Target::Ctl(CtlParam::EnvDecay)),
+    (Some(KnobPage::Arp), 12, Target::Ctl(CtlParam::EnvSustain)),
+    (Some(KnobPage::Arp), 13, Target::Ctl(CtlParam::EnvRelease)),
+    (Some(KnobPage::Arp), 14, Target::Ctl(CtlParam::MutateAmount)),
+    (Some(KnobPage::Arp), 15, Target::Ctl(CtlParam::PlayScale)),
+    (Some(KnobPage::Arp), 16, Target::Ctl(CtlParam::Takeover)),
+];
+
+fn default_mapping() -> Mapping<KnobPage, Target> {
+    let mut mapping = Mapping::default();
+    for (page, cc, target) in DEFAULT_MAP {
+        let assign = Some(Assign { target: *target, scale: target.scale() });
+        match page {
+            Some(page) => mapping.set(*page, *cc, assign),
+            None => for page in KnobPage::ALL {
+                mapping.set(page, *cc, assign)
+            }
+        }
+    }
+    mapping
+}
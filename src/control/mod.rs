@@ -0,0 +1,2 @@
+pub mod dw6_control;
+pub mod blinky_beat;
@@ -6,6 +6,7 @@
 #![allow(clippy::upper_case_acronyms)]
 
 use heapless::Vec;
+use num_enum::FromPrimitive;
 use midi::{U7, U4, Note, Program, Control, MidiChannel, MidiError};
 
 use crate::sysex::PatternExp::{Seq, Cap, Val};
@@ -38,10 +39,13 @@ const BEATSTEP: &[u8] = &[0x6B, 0x7F];
 //     Sysex::new(vec![Seq(DATA_HEADER), Buf(dump)])
 // }
 
-pub type LongSysex = SysexSeq<64>;
+pub type BeatstepSysex = SysexSeq<10>;
 
-fn parameter_set(sysex: &mut LongSysex, param: u8, control: u8, value: u8) {
-    sysex.extend_from_slices(&[ARTURIA, BEATSTEP, &[0x42, 0x02, 0x00, param, control, value]])
+/// Most params need many sysex messages, one per setting
+pub type BeatstepSysexList = Vec<BeatstepSysex, 6>;
+
+fn parameter_set(sysex: &mut BeatstepSysexList, param: u8, control: u8, value: u8) {
+    let _ = sysex.push(BeatstepSysex::from_slices(&[ARTURIA, BEATSTEP, &[0x42, 0x02, 0x00, param, control, value]]));
 }
 
 const MODE: u8 = 0x01;
@@ -51,8 +55,8 @@ const STEP_NOTE: u8 = 0x52;
 const STEP_ENABLED: u8 = 0x53;
 const SEQ: u8 = 0x50;
 
-pub fn beatstep_set(param: Param) -> LongSysex {
-    let mut sysex = LongSysex::new();
+pub fn beatstep_set(param: Param) -> BeatstepSysexList {
+    let mut sysex = BeatstepSysexList::new();
     match param {
         Param::PadOff(pad) =>
             parameter_set(&mut sysex, MODE, pad.control_code(), PadMode::Off as u8),
@@ -254,9 +258,11 @@ enum KnobMode {
 #[derive(Debug)]
 pub struct SeqTranspose(Note);
 
-#[derive(Debug)]
+#[derive(Debug, FromPrimitive, Copy, Clone, Eq, PartialEq)]
 #[repr(u8)]
+#[derive(defmt::Format)]
 pub enum SeqScale {
+    #[num_enum(default)]
     Chromatic,
     Major,
     Minor,
@@ -267,6 +273,24 @@ pub enum SeqScale {
     User,
 }
 
+impl SeqScale {
+    pub const COUNT: u8 = 8;
+
+    /// Semitones above root of each scale degree
+    /// User scale is set up on the Beatstep itself and unknown here, it is treated as chromatic
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            SeqScale::Chromatic | SeqScale::User => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            SeqScale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            SeqScale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            SeqScale::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            SeqScale::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            SeqScale::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            SeqScale::Blues => &[0, 3, 5, 6, 7, 10],
+        }
+    }
+}
+
 #[derive(Debug)]
 #[repr(u8)]
 pub enum SeqMode {
@@ -413,15 +437,31 @@ pub enum EncoderNRPNParam {
 pub type Minimum = U7;
 pub type Maximum = U7;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(u8)]
 pub enum Behavior {
     Absolute = 0,
+    /// Binary offset, 0x40 is no move
     RelativeCentered64 = 1,
+    /// Two's complement, 0x7F is one step down
     RelativeCentered0 = 2,
+    /// Offset 16, 0x10 is no move
     RelativeCentered16 = 3,
 }
 
+impl Behavior {
+    /// Signed number of steps sent by a relative encoder, None if absolute
+    pub fn step(&self, value: U7) -> Option<i8> {
+        match self {
+            Behavior::Absolute => None,
+            Behavior::RelativeCentered64 => Some(value.0 as i8 - 0x40),
+            // sign-extend 7 bit value
+            Behavior::RelativeCentered0 => Some((value.0 << 1) as i8 >> 1),
+            Behavior::RelativeCentered16 => Some(value.0 as i8 - 0x10),
+        }
+    }
+}
+
 #[derive(Debug)]
 #[repr(u8)]
 pub enum Granularity {
@@ -473,3 +513,45 @@ pub enum SeqGlobal {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(behavior: Behavior, value: u8) -> Option<i8> {
+        behavior.step(U7(value))
+    }
+
+    #[test]
+    fn absolute_has_no_step() {
+        assert_eq!(step(Behavior::Absolute, 0), None);
+        assert_eq!(step(Behavior::Absolute, 0x7F), None);
+    }
+
+    #[test]
+    fn centered_64_steps() {
+        assert_eq!(step(Behavior::RelativeCentered64, 0x00), Some(-64));
+        assert_eq!(step(Behavior::RelativeCentered64, 0x3F), Some(-1));
+        assert_eq!(step(Behavior::RelativeCentered64, 0x40), Some(0));
+        assert_eq!(step(Behavior::RelativeCentered64, 0x41), Some(1));
+        assert_eq!(step(Behavior::RelativeCentered64, 0x7F), Some(63));
+    }
+
+    #[test]
+    fn centered_0_steps_sign_extended() {
+        assert_eq!(step(Behavior::RelativeCentered0, 0x00), Some(0));
+        assert_eq!(step(Behavior::RelativeCentered0, 0x01), Some(1));
+        assert_eq!(step(Behavior::RelativeCentered0, 0x3F), Some(63));
+        assert_eq!(step(Behavior::RelativeCentered0, 0x40), Some(-64));
+        assert_eq!(step(Behavior::RelativeCentered0, 0x7F), Some(-1));
+    }
+
+    #[test]
+    fn centered_16_steps() {
+        assert_eq!(step(Behavior::RelativeCentered16, 0x00), Some(-16));
+        assert_eq!(step(Behavior::RelativeCentered16, 0x0F), Some(-1));
+        assert_eq!(step(Behavior::RelativeCentered16, 0x10), Some(0));
+        assert_eq!(step(Behavior::RelativeCentered16, 0x11), Some(1));
+        assert_eq!(step(Behavior::RelativeCentered16, 0x7F), Some(111));
+    }
+}
@@ -4,8 +4,12 @@
 #![allow(dead_code)]
 
 use heapless::Vec;
+use midi::MidiChannel;
+use num_enum::TryFromPrimitive;
 use crate::sysex::{PatternExp, ExpType, pattern_match, SysexSeq};
-use PatternExp::{Seq, Cap, Val};
+use crate::scale::{Curve, Scale};
+use crate::apps::param_queue::PackedParam;
+use PatternExp::{Cap, Val};
 use ExpType::*;
 
 const KORG: u8 = 0x42;
@@ -14,53 +18,91 @@ const DW_6000_ID: u8 = 0x04;
 const ID_FORMAT: u8 = 0x40;
 const DATA_FORMAT: u8 = 0x30;
 
-const WRITE_OK: u8 = 0x21;
-const WRITE_ERR: u8 = 0x22;
+pub const WRITE_OK: u8 = 0x21;
+pub const WRITE_ERR: u8 = 0x22;
 
-const ID_HEADER: &[u8] = &[KORG, ID_FORMAT];
-pub const DATA_HEADER: &[u8] = &[KORG, DATA_FORMAT, DW_6000_ID];
+/// Programs in the synth's memory, 8 banks of 8
+pub const PROGRAM_COUNT: usize = 64;
 
 pub type ShortDw6Sysex = SysexSeq<8>;
 pub type LongDw6Sysex = SysexSeq<32>;
 
-pub fn id_request_sysex() -> ShortDw6Sysex {
-    SysexSeq::from_slices(&[ID_HEADER])
+/// Reply to `id_request_sysex`, captures format and channel
+pub const ID_REPLY: &[PatternExp] = &[Val(KORG), Cap(Channel), Val(DW_6000_ID)];
+
+/// Reply to `dump_request_sysex`, captures format and channel, then the dump
+pub const DUMP_REPLY: &[PatternExp] = &[Val(KORG), Cap(Channel), Val(DW_6000_ID), Val(0x40), Cap(Bytes(26))];
+
+/// `store_program_sysex` itself, captures format and channel, then the program
+pub const STORE_REQUEST: &[PatternExp] = &[Val(KORG), Cap(Channel), Val(DW_6000_ID), Val(0x11), Cap(ValueU7)];
+
+/// Reply to `store_program_sysex`, captures format and channel, then WRITE_OK or WRITE_ERR
+pub const WRITE_REPLY: &[PatternExp] = &[Val(KORG), Cap(Channel), Val(DW_6000_ID), Cap(ValueU7)];
+
+/// Sysex format and channel are sent in the same byte
+fn data_header(channel: MidiChannel) -> [u8; 3] {
+    [KORG, DATA_FORMAT | channel.as_u8(), DW_6000_ID]
+}
+
+/// Channel the synth replied on, from a reply's format and channel byte
+pub fn reply_channel(format_channel: u8) -> Option<MidiChannel> {
+    match format_channel & 0xF0 {
+        ID_FORMAT | DATA_FORMAT => MidiChannel::try_from_primitive(format_channel & 0x0F).ok(),
+        _ => None,
+    }
 }
 
-pub fn id_matcher(buffer: &[u8]) -> bool {
-    let mut tokens: Vec<_, 0> = Vec::new();
-    pattern_match(buffer, &[Seq(ID_HEADER), Val(DW_6000_ID)], &mut tokens)
+pub fn id_request_sysex(channel: MidiChannel) -> ShortDw6Sysex {
+    SysexSeq::from_slices(&[&[KORG, ID_FORMAT | channel.as_u8()]])
+}
+
+pub fn id_matcher(buffer: &[u8]) -> Option<MidiChannel> {
+    let mut tokens: Vec<_, 1> = Vec::new();
+    if pattern_match(buffer, ID_REPLY, &mut tokens) {
+        return tokens.get(0).and_then(|(idx, _)| reply_channel(buffer[*idx]));
+    }
+    None
 }
 
-pub fn store_program_sysex(patch_idx: u8) -> ShortDw6Sysex {
-    SysexSeq::from_slices(&[DATA_HEADER, &[0x11, patch_idx]])
+pub fn store_program_sysex(channel: MidiChannel, patch_idx: u8) -> ShortDw6Sysex {
+    SysexSeq::from_slices(&[&data_header(channel), &[0x11, patch_idx]])
 }
 
-pub fn load_program_sysex(dump: &[u8]) -> LongDw6Sysex {
-    SysexSeq::from_slices(&[DATA_HEADER, dump])
+/// Loads into DW-6000 edit buffer, same format as the dump it sends
+pub fn load_program_sysex(channel: MidiChannel, dump: &[u8]) -> LongDw6Sysex {
+    SysexSeq::from_slices(&[&data_header(channel), &[0x40], dump])
 }
 
-pub fn set_parameter_sysex(param: u8, value: u8) -> ShortDw6Sysex {
-    SysexSeq::from_slices(&[DATA_HEADER, &[0x41, param, value]])
+pub fn set_parameter_sysex(channel: MidiChannel, param: u8, value: u8) -> ShortDw6Sysex {
+    SysexSeq::from_slices(&[&data_header(channel), &[0x41, param, value]])
 }
 
 pub fn match_write(buffer: &[u8], expected: u8) -> Result<bool, ()> {
-    let mut tokens: Vec<_, 1> = Vec::new();
-    if pattern_match(buffer, &[Seq(DATA_HEADER), Cap(ValueU7)], &mut tokens) {
-        Ok(tokens.get(0).map(|(idx, _)| buffer[*idx] == expected).unwrap_or(false))
+    let mut tokens: Vec<_, 2> = Vec::new();
+    if pattern_match(buffer, WRITE_REPLY, &mut tokens) {
+        Ok(tokens.get(1).map(|(idx, _)| buffer[*idx] == expected).unwrap_or(false))
     } else {
         Ok(false)
     }
 }
 
-pub fn dump_request_sysex() -> ShortDw6Sysex {
-    SysexSeq::from_slices(&[DATA_HEADER, &[0x10]])
+pub fn dump_request_sysex(channel: MidiChannel) -> ShortDw6Sysex {
+    SysexSeq::from_slices(&[&data_header(channel), &[0x10]])
+}
+
+/// Program written by a `store_program_sysex`, e.g. from a recorded bank backup
+pub fn store_matcher(buffer: &[u8]) -> Option<u8> {
+    let mut tokens: Vec<_, 2> = Vec::new();
+    if pattern_match(buffer, STORE_REQUEST, &mut tokens) {
+        return tokens.get(1).map(|(idx, _)| buffer[*idx]);
+    }
+    None
 }
 
 pub fn dump_matcher(buffer: &[u8]) -> Option<&[u8]> {
-    let mut tokens: Vec<_, 1> = Vec::new();
-    if pattern_match(buffer, &[Seq(DATA_HEADER), Val(0x40), Cap(Bytes(26))], &mut tokens) {
-        return tokens.get(0).map(|(idx, _)| &buffer[*idx..]);
+    let mut tokens: Vec<_, 2> = Vec::new();
+    if pattern_match(buffer, DUMP_REPLY, &mut tokens) {
+        return tokens.get(1).map(|(idx, _)| &buffer[*idx..]);
     }
     None
 }
@@ -142,8 +184,8 @@ pub struct Dw6Dump {
     pub osc2_interval_osc2_detune: IntervalOsc2Detune,
 }
 
-pub fn as_dump_ref_mut(buf: &[u8]) -> &mut Dw6Dump {
-    let p: *mut Dw6Dump = buf.as_ptr() as *mut Dw6Dump;
+pub fn as_dump_ref_mut(buf: &mut [u8]) -> &mut Dw6Dump {
+    let p: *mut Dw6Dump = buf.as_mut_ptr() as *mut Dw6Dump;
     unsafe { &mut *p }
 }
 
@@ -194,7 +236,7 @@ pub fn get_param_value(param: Dw6Param, dump_buf: &[u8]) -> u8 {
     }
 }
 
-pub fn set_param_value(param: Dw6Param, value: u8, dump_buf: &[u8]) {
+pub fn set_param_value(param: Dw6Param, value: u8, dump_buf: &mut [u8]) {
     use Dw6Param::*;
     let dump = as_dump_ref_mut(dump_buf);
     match param {
@@ -236,7 +278,67 @@ pub fn set_param_value(param: Dw6Param, value: u8, dump_buf: &[u8]) {
     }
 }
 
+impl PackedParam for Dw6Param {
+    fn index(&self) -> u8 {
+        Dw6Param::index(self)
+    }
+
+    fn get(&self, dump: &[u8]) -> u8 {
+        get_param_value(*self, dump)
+    }
+
+    fn set(&self, value: u8, dump: &mut [u8]) {
+        set_param_value(*self, value, dump)
+    }
+}
+
 impl Dw6Param {
+    pub const ALL: [Dw6Param; 35] = {
+        use Dw6Param::*;
+        [
+            Osc1Wave, Osc1Level, Osc1Octave, Osc2Wave, Osc2Level, Osc2Octave, Osc2Detune, Interval, Noise,
+            Cutoff, Resonance,
+            VcfInt, VcfAttack, VcfDecay, VcfBreak, VcfSlope, VcfSustain, VcfRelease,
+            VcaAttack, VcaDecay, VcaBreak, VcaSlope, VcaSustain, VcaRelease,
+            BendVcf, BendOsc, AssignMode, Portamento,
+            MgFreq, MgDelay, MgOsc, MgVcf,
+            KbdTrack, Polarity, Chorus,
+        ]
+    };
+
+    /// Sysex param number, also the dump byte holding the param's value
+    pub fn index(&self) -> u8 {
+        use Dw6Param::*;
+        match self {
+            AssignMode | BendOsc => 0,
+            Portamento => 1,
+            Osc1Level => 2,
+            Osc2Level => 3,
+            Noise => 4,
+            Cutoff => 5,
+            Resonance => 6,
+            VcfInt => 7,
+            VcfAttack => 8,
+            VcfDecay => 9,
+            VcfBreak => 10,
+            VcfSlope => 11,
+            VcfSustain => 12,
+            VcfRelease => 13,
+            VcaAttack => 14,
+            VcaDecay => 15,
+            VcaBreak => 16,
+            VcaSlope => 17,
+            BendVcf | VcaSustain => 18,
+            Osc1Octave | VcaRelease => 19,
+            Osc2Octave | MgFreq => 20,
+            KbdTrack | MgDelay => 21,
+            Polarity | MgOsc => 22,
+            Chorus | MgVcf => 23,
+            Osc1Wave | Osc2Wave => 24,
+            Osc2Detune | Interval => 25,
+        }
+    }
+
     pub fn max_value(&self) -> u8 {
         use Dw6Param::*;
         match self {
@@ -261,6 +363,26 @@ impl Dw6Param {
         }
     }
 
+    /// How knob travel maps to param values
+    pub fn scale(&self) -> Scale {
+        use Dw6Param::*;
+        let max = self.max_value();
+        match self {
+            Osc1Wave | Osc2Wave | Osc1Octave | Osc2Octave | Interval | Osc2Detune |
+            AssignMode | KbdTrack | BendOsc |
+            Polarity | Chorus | BendVcf => Scale::new(max, Curve::Stepped),
+
+            Portamento | MgDelay |
+            VcfAttack | VcfDecay | VcfSlope | VcfRelease |
+            VcaAttack | VcaDecay | VcaSlope | VcaRelease => Scale::new(max, Curve::Exp),
+
+            // easy to get back to no modulation
+            Noise | VcfInt | MgOsc | MgVcf => Scale::new(max, Curve::Linear).with_detent(0),
+
+            _ => Scale::new(max, Curve::Linear),
+        }
+    }
+
     pub fn dump_index(&self) -> usize {
         use Dw6Param::*;
         match self {
@@ -466,3 +588,53 @@ bitfield! {
     pub osc2_interval, set_osc2_interval: 5,3;
     pub osc2_detune, set_osc2_detune: 2,0;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump_reply() -> Vec<u8, 30> {
+        let mut reply = Vec::from_slice(&[KORG, DATA_FORMAT | 2, DW_6000_ID, 0x40]).unwrap();
+        for i in 0..26 {
+            reply.push(i).unwrap();
+        }
+        reply
+    }
+
+    #[test]
+    fn dump_reply_matches() {
+        let reply = dump_reply();
+        let dump = dump_matcher(&reply).unwrap();
+        assert_eq!(dump.len(), 26);
+        assert_eq!(dump[0], 0);
+        assert_eq!(dump[25], 25);
+    }
+
+    #[test]
+    fn store_matches() {
+        assert_eq!(store_matcher(&[KORG, DATA_FORMAT | 2, DW_6000_ID, 0x11, 42]), Some(42));
+        assert_eq!(store_matcher(&dump_reply()), None);
+    }
+
+    #[test]
+    fn short_dump_rejected() {
+        let reply = dump_reply();
+        assert!(dump_matcher(&reply[..29]).is_none());
+    }
+
+    #[test]
+    fn write_ack() {
+        let ok = [KORG, DATA_FORMAT | 2, DW_6000_ID, WRITE_OK];
+        let err = [KORG, DATA_FORMAT | 2, DW_6000_ID, WRITE_ERR];
+        assert_eq!(match_write(&ok, WRITE_OK), Ok(true));
+        assert_eq!(match_write(&err, WRITE_OK), Ok(false));
+        assert_eq!(match_write(&err, WRITE_ERR), Ok(true));
+        assert_eq!(match_write(&ok[..3], WRITE_OK), Ok(false));
+    }
+
+    #[test]
+    fn id_reply_channel() {
+        let reply = [KORG, ID_FORMAT | 4, DW_6000_ID];
+        assert_eq!(id_matcher(&reply).map(|ch| ch as u8), Some(4));
+    }
+}
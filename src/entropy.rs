@@ -0,0 +1,23 @@
+//! Seeds for the apps' generators, from the hardware RNG if `rng` feature is enabled
+
+use crate::chaos;
+
+/// Get a seed for a new generator
+#[cfg(feature = "rng")]
+pub async fn seed() -> u64 {
+    let mut seed = [0; 8];
+    let mut chaos = crate::CHAOS.lock().await;
+    if let Some(rng) = chaos.get_mut() {
+        if rng.async_fill_bytes(&mut seed).await.is_ok() {
+            return u64::from_le_bytes(seed);
+        }
+    }
+    warn!("hardware RNG unavailable, using fixed seed");
+    chaos::FIXED_SEED
+}
+
+/// Get a seed for a new generator
+#[cfg(not(feature = "rng"))]
+pub async fn seed() -> u64 {
+    chaos::FIXED_SEED
+}
@@ -0,0 +1,38 @@
+//! Hardware independent parts of the controller: device models, sysex matching and the apps' logic
+//! Built for the host when testing, see README
+
+#![cfg_attr(not(test), no_std)]
+// std's float methods take over micromath's on the host
+#![cfg_attr(test, allow(unused_imports))]
+
+extern crate embedded_midi as midi;
+
+#[macro_use]
+extern crate bitfield;
+
+pub mod apps;
+pub mod chaos;
+pub mod devices;
+pub mod scale;
+pub mod sysex;
+
+/// defmt output is dropped in host tests, panics go to the test harness
+#[cfg(test)]
+mod test_logger {
+    #[defmt::global_logger]
+    struct Logger;
+
+    unsafe impl defmt::Logger for Logger {
+        fn acquire() {}
+        unsafe fn flush() {}
+        unsafe fn release() {}
+        unsafe fn write(_bytes: &[u8]) {}
+    }
+
+    defmt::timestamp!("");
+
+    #[defmt::panic_handler]
+    fn panic() -> ! {
+        core::panic!("defmt panic")
+    }
+}
@@ -8,9 +8,6 @@ extern crate embedded_midi as midi;
 // #[macro_use]
 // extern crate alloc;
 
-#[macro_use]
-extern crate bitfield;
-
 #[macro_use]
 extern crate defmt;
 
@@ -35,6 +32,8 @@ use embassy_stm32::time::mhz;
 use embassy_stm32::usb_otg::Driver;
 
 use embassy_usb::{UsbDevice};
+#[cfg(feature = "usb")]
+use static_cell::StaticCell;
 
 use crate::port::midi_usb;
 use crate::port::midi_usb::MidiClass;
@@ -49,11 +48,12 @@ use midi::MidiChannel::CH1;
 use crate::port::serial_buffered::{BufferedSerialMidiIn, BufferedSerialMidiOut};
 use crate::resource::Shared;
 
+use dv6::{apps, chaos, devices, scale, sysex};
+
 mod resource;
-mod apps;
-mod devices;
+mod control;
+mod entropy;
 mod port;
-mod sysex;
 mod allocator;
 mod log_defmt;
 // mod display;
@@ -195,21 +195,25 @@ async fn main(spawner: Spawner) {
 
     info!("Boot seq icache:{} dcache:{} fpu: {}", SCB::icache_enabled(), SCB::dcache_enabled(), SCB::fpu_access_mode() == FpuAccessMode::Enabled);
 
-    // Generate random seed.
+    // Hardware RNG, seeds the apps' PRNGs (see chaos::seed)
     #[cfg(feature = "rng")]
     {
         // FIXME hardfaults on devebox, blackpill
-        let mut rng: rng::Rng<'_, RNG> = Rng::new(p.RNG, Irqs);
-        let mut seed = [0; 8];
-        let _ = rng.async_fill_bytes(&mut seed).await;
-        let _seed = u64::from_le_bytes(seed);
-        CHAOS.init_static(rng);
+        let rng: rng::Rng<'static, RNG> = Rng::new(p.RNG, Irqs);
+        let _ = CHAOS.lock().await.set(rng);
     }
 
     #[cfg(feature = "usb")]
     {
+        static EP_OUT_BUFFER: StaticCell<[u8; 256]> = StaticCell::new();
+        static CONFIG_DESC: StaticCell<[u8; 256]> = StaticCell::new();
+        static BOS_DESC: StaticCell<[u8; 256]> = StaticCell::new();
+        static MSOS_DESC: StaticCell<[u8; 256]> = StaticCell::new();
+        static CONTROL_BUF: StaticCell<[u8; 128]> = StaticCell::new();
+        static MIDI_STATE: StaticCell<midi_usb::State<'static>> = StaticCell::new();
+
         // Create the driver, from the HAL.
-        let ep_out_buffer = &mut make_static!([0; 256])[..];
+        let ep_out_buffer = &mut EP_OUT_BUFFER.init([0; 256])[..];
         let mut config = usb_otg::Config::default();
         config.vbus_detection = true;
         let driver = Driver::new_fs(p.USB_OTG_FS, Irqs, p.PA12, p.PA11, ep_out_buffer, config);
@@ -231,18 +235,18 @@ async fn main(spawner: Spawner) {
         let mut usb_builder = embassy_usb::Builder::new(
             driver,
             usb_cfg,
-            &mut make_static!([0; 256])[..],
-            &mut make_static!([0; 256])[..],
-            &mut make_static!([0; 256])[..],
-            &mut make_static!([0; 128])[..],
+            &mut CONFIG_DESC.init([0; 256])[..],
+            &mut BOS_DESC.init([0; 256])[..],
+            &mut MSOS_DESC.init([0; 256])[..],
+            &mut CONTROL_BUF.init([0; 128])[..],
         );
 
-        let usb_midi_state = make_static!(midi_usb::State::new());
+        let usb_midi_state = MIDI_STATE.init(midi_usb::State::new());
         let usb_midi = MidiClass::new(&mut usb_builder, usb_midi_state, 64);
         let usb_bus = usb_builder.build();
         let (tx, rx) = usb_midi.split();
-        MIDI_USB_1_OUT.init_static(tx);
-        MIDI_USB_1_IN.init_static(rx);
+        let _ = MIDI_USB_1_OUT.lock().await.set(tx);
+        let _ = MIDI_USB_1_IN.lock().await.set(rx);
 
         unwrap!(spawner.spawn(usb_task(usb_bus)));
     }
@@ -274,7 +278,7 @@ async fn main(spawner: Spawner) {
     // unwrap!(spawner.spawn(print_uart5()));
 
     use midi::Note::*;
-    apps::blinky_beat::start_app(CH1, &[
+    control::blinky_beat::start_app(CH1, &[
         D0,
         Ds0,
         E0,
@@ -292,7 +296,7 @@ async fn main(spawner: Spawner) {
         E1,
         F1,
     ], spawner).await.unwrap();
-    apps::dw6_control::start_app(spawner).await.unwrap();
+    control::dw6_control::start_app(spawner).await.unwrap();
 
     let mut led = Output::new(p.PA1, Level::High, Speed::Low);
 
@@ -0,0 +1,176 @@
+//! Maps 7-bit knob travel onto a parameter's native range, and back
+//! Reverse mapping gives the knob position matching a value, for display and takeover
+
+use micromath::F32Ext;
+
+const KNOB_MAX: u8 = 127;
+
+/// Knob positions this close to a detent value's position snap to it
+const DETENT_WIDTH: u8 = 4;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(defmt::Format)]
+pub enum Curve {
+    Linear,
+    /// Finer control at low values, for times
+    Exp,
+    /// Equal knob travel for each value, for switches and selectors
+    Stepped,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[derive(defmt::Format)]
+pub struct Scale {
+    /// Value at knob fully down, to restrict a knob to part of the parameter's range
+    pub min: u8,
+    pub max: u8,
+    pub curve: Curve,
+    /// Value that gets a wider share of knob travel
+    pub detent: Option<u8>,
+}
+
+impl Scale {
+    pub const fn new(max: u8, curve: Curve) -> Self {
+        Self { min: 0, max, curve, detent: None }
+    }
+
+    pub const fn with_min(self, min: u8) -> Self {
+        Self { min, ..self }
+    }
+
+    pub const fn with_detent(self, detent: u8) -> Self {
+        Self { detent: Some(detent), ..self }
+    }
+
+    /// Parameter value for a knob position
+    pub fn from_cc(&self, cc: u8) -> u8 {
+        let cc = cc.min(KNOB_MAX);
+        if let Some(detent) = self.detent.filter(|d| (self.min..=self.max).contains(d)) {
+            if cc.abs_diff(self.curve_to_cc(detent - self.min)) <= DETENT_WIDTH {
+                return detent;
+            }
+        }
+        self.min + self.curve_from_cc(cc)
+    }
+
+    /// Knob position for a parameter value
+    pub fn to_cc(&self, value: u8) -> u8 {
+        self.curve_to_cc(value.max(self.min).min(self.max).saturating_sub(self.min))
+    }
+
+    fn span(&self) -> u8 {
+        self.max.saturating_sub(self.min)
+    }
+
+    fn curve_from_cc(&self, cc: u8) -> u8 {
+        let max = self.span() as u16;
+        match self.curve {
+            Curve::Linear => ((cc as u16 * max + KNOB_MAX as u16 / 2) / KNOB_MAX as u16) as u8,
+            Curve::Exp => {
+                let x = cc as f32 / KNOB_MAX as f32;
+                (x * x * max as f32 + 0.5) as u8
+            }
+            Curve::Stepped => (cc as u16 * (max + 1) / (KNOB_MAX as u16 + 1)) as u8,
+        }
+    }
+
+    fn curve_to_cc(&self, value: u8) -> u8 {
+        if self.span() == 0 {
+            return 0;
+        }
+        let max = self.span() as u16;
+        match self.curve {
+            Curve::Linear => ((value as u16 * KNOB_MAX as u16 + max / 2) / max) as u8,
+            Curve::Exp => {
+                let x = (value as f32 / max as f32).sqrt();
+                (x * KNOB_MAX as f32 + 0.5) as u8
+            }
+            // middle of the value's notch
+            Curve::Stepped => ((value as u16 * 2 + 1) * (KNOB_MAX as u16 + 1) / (max + 1) / 2) as u8,
+        }
+    }
+}
+
+/// Value between `from` and `to` at `position` out of `max`, rounded to nearest
+/// `to` may be lower than `from`
+pub fn interpolate(from: u8, to: u8, position: u8, max: u8) -> u8 {
+    if max == 0 {
+        return to;
+    }
+    let from = from as i16;
+    let to = to as i16;
+    let pos = position.min(max) as i16;
+    let max = max as i16;
+    (from + ((to - from) * pos * 2 + max * (to - from).signum()) / (max * 2)) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for curve in [Curve::Linear, Curve::Exp, Curve::Stepped] {
+            for max in [1, 3, 7, 31, 63] {
+                let scale = Scale::new(max, curve);
+                for value in 0..=max {
+                    assert_eq!(scale.from_cc(scale.to_cc(value)), value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn full_travel() {
+        for curve in [Curve::Linear, Curve::Exp, Curve::Stepped] {
+            let scale = Scale::new(63, curve);
+            assert_eq!(scale.from_cc(0), 0);
+            assert_eq!(scale.from_cc(KNOB_MAX), 63);
+        }
+    }
+
+    #[test]
+    fn stepped_equal_travel() {
+        let scale = Scale::new(3, Curve::Stepped);
+        assert_eq!(scale.from_cc(31), 0);
+        assert_eq!(scale.from_cc(32), 1);
+        assert_eq!(scale.from_cc(95), 2);
+        assert_eq!(scale.from_cc(96), 3);
+    }
+
+    #[test]
+    fn detent_snaps() {
+        let scale = Scale::new(63, Curve::Linear).with_detent(32);
+        let center = scale.to_cc(32);
+        assert_eq!(scale.from_cc(center - DETENT_WIDTH), 32);
+        assert_eq!(scale.from_cc(center + DETENT_WIDTH), 32);
+        assert!(scale.from_cc(center - DETENT_WIDTH - 1) < 32);
+        assert!(scale.from_cc(center + DETENT_WIDTH + 1) > 32);
+    }
+
+    #[test]
+    fn min_restricts_range() {
+        let scale = Scale::new(63, Curve::Linear).with_min(10);
+        assert_eq!(scale.from_cc(0), 10);
+        assert_eq!(scale.from_cc(KNOB_MAX), 63);
+        assert_eq!(scale.to_cc(5), 0);
+        for value in 10..=63 {
+            assert_eq!(scale.from_cc(scale.to_cc(value)), value);
+        }
+        // detent outside of range is ignored
+        let scale = scale.with_detent(0);
+        assert_eq!(scale.from_cc(0), 10);
+    }
+
+    #[test]
+    fn interpolate_both_ways() {
+        assert_eq!(interpolate(10, 20, 0, 127), 10);
+        assert_eq!(interpolate(10, 20, 127, 127), 20);
+        assert_eq!(interpolate(10, 20, 64, 127), 15);
+        assert_eq!(interpolate(20, 10, 0, 127), 20);
+        assert_eq!(interpolate(20, 10, 127, 127), 10);
+        assert_eq!(interpolate(20, 10, 64, 127), 15);
+        // past the end stays there
+        assert_eq!(interpolate(10, 20, 200, 127), 20);
+    }
+}
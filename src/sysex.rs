@@ -1,9 +1,17 @@
 
-use midi::{Packet, MidiMessage, PacketList};
+use midi::{Packet, MidiMessage, PacketList, MidiError};
 
+use core::cell::Cell;
+use core::future::Future;
 use core::iter::FromIterator;
 use heapless::Vec;
 
+use embassy_sync::blocking_mutex;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, with_timeout};
+
 /// Used to send sysex
 /// Accepts same Token as matcher for convenience, but only Match and Val value are sent
 #[derive(Debug)]
@@ -112,18 +120,17 @@ pub enum PatternExp {
 pub fn pattern_match<const N: usize>(sysex_buffer: &[u8], pattern: &[PatternExp], captured: &mut Vec<(usize, ExpType), N>) -> bool {
     let mut pos = 0;
 
-    let mut buffer = sysex_buffer;
-
     for exp in pattern {
         if pos > sysex_buffer.len() { return false; }
-        buffer = &sysex_buffer[pos..];
+        // rest of the buffer, from the current position
+        let buffer = &sysex_buffer[pos..];
         match exp {
             PatternExp::Skip(len) => pos += len,
-            PatternExp::Val(token) => if *token == buffer[0] { pos += 1 } else { return false; }
+            PatternExp::Val(token) => if buffer.first() == Some(token) { pos += 1 } else { return false; }
             PatternExp::Seq(seq) =>
                 if buffer.starts_with(seq) { pos += seq.len() } else { return false; }
             PatternExp::Cap(exp_type) => {
-                if pos + exp_type.len() > buffer.len() { return false; }
+                if exp_type.len() > buffer.len() { return false; }
                 captured.push((pos, *exp_type)).expect("sysex capture buffer overflow");
                 pos += exp_type.len();
             }
@@ -133,3 +140,233 @@ pub fn pattern_match<const N: usize>(sysex_buffer: &[u8], pattern: &[PatternExp]
     pos == sysex_buffer.len()
 }
 
+
+/// Max number of fields captured from a reply
+const MAX_CAPTURES: usize = 8;
+
+#[derive(Debug)]
+#[derive(defmt::Format)]
+pub enum TransactionError {
+    Midi(MidiError),
+    /// No matching reply, after all attempts
+    Timeout,
+}
+
+impl From<MidiError> for TransactionError {
+    fn from(err: MidiError) -> Self {
+        TransactionError::Midi(err)
+    }
+}
+
+/// Sysex reply to a request, with the fields captured by the reply pattern
+#[derive(Debug)]
+pub struct Reply<const N: usize> {
+    bytes: Vec<u8, N>,
+    captured: Vec<(usize, ExpType), MAX_CAPTURES>,
+}
+
+impl<const N: usize> Reply<N> {
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Bytes of the nth captured field
+    pub fn field(&self, idx: usize) -> Option<&[u8]> {
+        self.captured.get(idx).map(|(pos, exp)| &self.bytes[*pos..*pos + exp.len()])
+    }
+}
+
+/// Request / reply exchanges with a device, one at a time
+/// The device's receive task offers every sysex it captures, the one matching the awaited pattern goes to the requester
+/// Only the DW-6000 replies go through one, nothing reads Beatstep settings (`beatstep_control_get`) yet
+pub struct Transaction<const N: usize> {
+    // a request waits for the previous one to complete
+    lock: Mutex<ThreadModeRawMutex, ()>,
+    expect: blocking_mutex::Mutex<ThreadModeRawMutex, Cell<Option<&'static [PatternExp]>>>,
+    reply: Signal<ThreadModeRawMutex, Vec<u8, N>>,
+}
+
+impl<const N: usize> Transaction<N> {
+    pub const fn new() -> Self {
+        Self {
+            lock: Mutex::new(()),
+            expect: blocking_mutex::Mutex::new(Cell::new(None)),
+            reply: Signal::new(),
+        }
+    }
+
+    /// Received sysex, returns true if it was the awaited reply
+    pub fn offer(&self, sysex: &[u8]) -> bool {
+        let Some(pattern) = self.expect.lock(|e| e.get()) else {
+            return false;
+        };
+        let mut captured: Vec<_, MAX_CAPTURES> = Vec::new();
+        if !pattern_match(sysex, pattern, &mut captured) {
+            return false;
+        }
+        match Vec::from_slice(sysex) {
+            Ok(bytes) => {
+                self.expect.lock(|e| e.set(None));
+                self.reply.signal(bytes);
+                true
+            }
+            Err(_) => false
+        }
+    }
+
+    /// Send request and wait for a reply matching pattern, request is sent again after each timeout
+    /// Gives up after `attempts` sends (at least one)
+    pub async fn request<F, R>(&self, mut send: F, pattern: &'static [PatternExp], timeout: Duration, attempts: usize) -> Result<Reply<N>, TransactionError>
+        where F: FnMut() -> R, R: Future<Output=Result<(), MidiError>>
+    {
+        let _guard = self.lock.lock().await;
+        for _ in 0..attempts.max(1) {
+            self.reply.reset();
+            self.expect.lock(|e| e.set(Some(pattern)));
+            if let Err(err) = send().await {
+                self.expect.lock(|e| e.set(None));
+                return Err(err.into());
+            }
+            if let Ok(bytes) = with_timeout(timeout, self.reply.wait()).await {
+                let mut captured = Vec::new();
+                pattern_match(&bytes, pattern, &mut captured);
+                return Ok(Reply { bytes, captured });
+            }
+        }
+        self.expect.lock(|e| e.set(None));
+        Err(TransactionError::Timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PatternExp::{Cap, Seq, Skip};
+
+    const HEADER: &[u8] = &[0x00, 0x20, 0x6B];
+    const REPLY: &[PatternExp] = &[Seq(HEADER), Cap(ExpType::ParamId), Skip(1), Cap(ExpType::Bytes(2))];
+
+    #[test]
+    fn captures_after_seq() {
+        let mut captured: Vec<_, 2> = Vec::new();
+        assert!(pattern_match(&[0x00, 0x20, 0x6B, 0x01, 0x7F, 0x02, 0x03], REPLY, &mut captured));
+        assert!(captured == [(3, ExpType::ParamId), (5, ExpType::Bytes(2))]);
+    }
+
+    #[test]
+    fn wrong_prefix_or_length_rejected() {
+        let mut captured: Vec<_, 2> = Vec::new();
+        assert!(!pattern_match(&[0x00, 0x21, 0x6B, 0x01, 0x7F, 0x02, 0x03], REPLY, &mut captured));
+        captured.clear();
+        assert!(!pattern_match(&[0x00, 0x20, 0x6B, 0x01, 0x7F, 0x02], REPLY, &mut captured));
+        captured.clear();
+        assert!(!pattern_match(&[0x00, 0x20, 0x6B, 0x01, 0x7F, 0x02, 0x03, 0x04], REPLY, &mut captured));
+        captured.clear();
+        assert!(!pattern_match(&[0x00, 0x20], REPLY, &mut captured));
+    }
+
+    #[test]
+    fn reply_fields() {
+        let mut captured = Vec::new();
+        let bytes: Vec<u8, 8> = Vec::from_slice(&[0x00, 0x20, 0x6B, 0x01, 0x7F, 0x02, 0x03]).unwrap();
+        assert!(pattern_match(&bytes, REPLY, &mut captured));
+        let reply = Reply { bytes, captured };
+        assert_eq!(reply.field(0), Some(&[0x01][..]));
+        assert_eq!(reply.field(1), Some(&[0x02, 0x03][..]));
+        assert_eq!(reply.field(2), None);
+    }
+
+    /// Thread mode mutexes only lock on a thread named main when hosted, test threads are not
+    fn on_main_thread(test: impl FnOnce() + Send + 'static) {
+        std::thread::Builder::new().name("main".into()).spawn(test).unwrap().join().unwrap();
+    }
+
+    /// Polls until done, mock time moves on while the future waits
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        let mut cx = core::task::Context::from_waker(core::task::Waker::noop());
+        loop {
+            if let core::task::Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            embassy_time::MockDriver::get().advance(Duration::from_millis(10));
+        }
+    }
+
+    const REQUEST_HEADER: &[u8] = &[0x00, 0x20, 0x6B, 0x01];
+    const REQUEST_REPLY: &[PatternExp] = &[Seq(REQUEST_HEADER), Cap(ExpType::ValueU7)];
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn matching_reply_returned() {
+        on_main_thread(|| {
+            let transaction: Transaction<8> = Transaction::new();
+            let reply = block_on(transaction.request(|| {
+                // other traffic is left to the receive task
+                assert!(!transaction.offer(&[0x00, 0x20, 0x6B, 0x02, 0x10]));
+                assert!(transaction.offer(&[0x00, 0x20, 0x6B, 0x01, 0x10]));
+                // only the first match is the reply
+                assert!(!transaction.offer(&[0x00, 0x20, 0x6B, 0x01, 0x11]));
+                async { Ok(()) }
+            }, REQUEST_REPLY, TIMEOUT, 1)).unwrap();
+            assert_eq!(reply.bytes(), &[0x00, 0x20, 0x6B, 0x01, 0x10]);
+            assert_eq!(reply.field(0), Some(&[0x10][..]));
+        });
+    }
+
+    #[test]
+    fn nothing_offered_outside_request() {
+        on_main_thread(|| {
+            let transaction: Transaction<8> = Transaction::new();
+            assert!(!transaction.offer(&[0x00, 0x20, 0x6B, 0x01, 0x10]));
+        });
+    }
+
+    #[test]
+    fn times_out_after_all_attempts() {
+        on_main_thread(|| {
+            let transaction: Transaction<8> = Transaction::new();
+            let sent = Cell::new(0);
+            let result = block_on(transaction.request(|| {
+                sent.set(sent.get() + 1);
+                async { Ok(()) }
+            }, REQUEST_REPLY, TIMEOUT, 3));
+            assert!(matches!(result, Err(TransactionError::Timeout)));
+            assert_eq!(sent.get(), 3);
+            // late reply is not taken
+            assert!(!transaction.offer(&[0x00, 0x20, 0x6B, 0x01, 0x10]));
+        });
+    }
+
+    #[test]
+    fn retried_until_reply() {
+        on_main_thread(|| {
+            let transaction: Transaction<8> = Transaction::new();
+            let sent = Cell::new(0);
+            let reply = block_on(transaction.request(|| {
+                sent.set(sent.get() + 1);
+                if sent.get() == 2 {
+                    transaction.offer(&[0x00, 0x20, 0x6B, 0x01, 0x10]);
+                }
+                async { Ok(()) }
+            }, REQUEST_REPLY, TIMEOUT, 3)).unwrap();
+            assert_eq!(reply.field(0), Some(&[0x10][..]));
+            assert_eq!(sent.get(), 2);
+        });
+    }
+
+    #[test]
+    fn send_error_not_retried() {
+        on_main_thread(|| {
+            let transaction: Transaction<8> = Transaction::new();
+            let sent = Cell::new(0);
+            let result = block_on(transaction.request(|| {
+                sent.set(sent.get() + 1);
+                async { Err(MidiError::WriteError) }
+            }, REQUEST_REPLY, TIMEOUT, 3));
+            assert!(matches!(result, Err(TransactionError::Midi(MidiError::WriteError))));
+            assert_eq!(sent.get(), 1);
+        });
+    }
+}